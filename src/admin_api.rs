@@ -0,0 +1,156 @@
+use axum::{
+    extract::{Path, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    del, insert_user, list, logout_all, password, registry::AuthorizedClients, roles::Role, set_role, UserDatabase,
+};
+
+#[derive(Clone)]
+struct ApiState {
+    database: Arc<Mutex<UserDatabase>>,
+    clients: AuthorizedClients,
+    api_key_hash: [u8; 32],
+}
+
+#[derive(Deserialize)]
+struct NewUser {
+    username: String,
+    password: String,
+    #[serde(default)]
+    role: Role,
+}
+
+fn error(status: StatusCode, message: impl Into<String>) -> Response {
+    (status, Json(json!({"status": "err", "message": message.into()}))).into_response()
+}
+
+fn not_saved(e: io::Error) -> Response {
+    eprintln!("Ошибка при сохранении базы пользователей: {:?}", e);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "Changes were applied but could not be saved to disk.")
+}
+
+/// Обслуживает HTTP API управления пользователями. Все маршруты, кроме
+/// `/health`, требуют заголовок `Authorization: Bearer <admin_api_key>`.
+pub async fn serve(
+    listener: TcpListener,
+    database: Arc<Mutex<UserDatabase>>,
+    clients: AuthorizedClients,
+    api_key: String,
+    shutdown: CancellationToken,
+) {
+    let state = ApiState {
+        database,
+        clients,
+        api_key_hash: Sha256::digest(api_key.as_bytes()).into(),
+    };
+
+    let protected = Router::new()
+        .route("/users", get(list_users).post(create_user))
+        .route("/users/{name}", delete(delete_user))
+        .route("/users/{name}/sessions", delete(delete_sessions))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_api_key));
+
+    let app = Router::new()
+        .route("/health", get(health))
+        .merge(protected)
+        .with_state(state);
+
+    if let Err(e) = axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await
+    {
+        eprintln!("Ошибка HTTP API управления: {:?}", e);
+    }
+}
+
+async fn require_api_key(State(state): State<ApiState>, request: Request, next: Next) -> Response {
+    let provided = request.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    // Сравниваются хэши ключей, поэтому время сравнения не выдаёт совпадающий префикс.
+    match provided {
+        Some(key) if <[u8; 32]>::from(Sha256::digest(key.as_bytes())) == state.api_key_hash => {
+            next.run(request).await
+        }
+        _ => error(StatusCode::UNAUTHORIZED, "Missing or invalid admin API key."),
+    }
+}
+
+async fn health() -> Response {
+    Json(json!({"status": "ok"})).into_response()
+}
+
+async fn list_users(State(state): State<ApiState>) -> Response {
+    let users = list(&state.database.lock().unwrap()).unwrap_or_default();
+    Json(json!({"status": "ok", "data": users})).into_response()
+}
+
+async fn create_user(State(state): State<ApiState>, Json(user): Json<NewUser>) -> Response {
+    if user.username.is_empty() || user.password.is_empty() {
+        return error(StatusCode::BAD_REQUEST, "Fields 'username' and 'password' must not be empty.");
+    }
+    // Хэширование пароля занимает заметное время, поэтому выполняется вне рабочих
+    // потоков runtime и до блокировки базы.
+    let result = tokio::task::spawn_blocking(move || -> io::Result<Option<String>> {
+        let password_hash = password::hash_password(&user.password);
+        let mut db = state.database.lock().unwrap();
+        let Some(message) = insert_user(&mut db, user.username.clone(), password_hash)? else {
+            return Ok(None);
+        };
+        set_role(&mut db, &state.clients, user.username, user.role)?;
+        Ok(Some(message))
+    })
+    .await;
+
+    match result {
+        Ok(Ok(Some(message))) => (StatusCode::CREATED, Json(json!({"status": "ok", "message": message}))).into_response(),
+        Ok(Ok(None)) => error(StatusCode::CONFLICT, "User already exists."),
+        Ok(Err(e)) => not_saved(e),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create user."),
+    }
+}
+
+// Запись базы ждёт диска, поэтому удаление тоже выполняется вне рабочих потоков runtime.
+async fn delete_user(State(state): State<ApiState>, Path(name): Path<String>) -> Response {
+    let result = tokio::task::spawn_blocking(move || {
+        del(&mut state.database.lock().unwrap(), &state.clients, name)
+    })
+    .await;
+
+    match result {
+        Ok(Ok(Some(_))) => StatusCode::NO_CONTENT.into_response(),
+        Ok(Ok(None)) => error(StatusCode::NOT_FOUND, "User not found."),
+        Ok(Err(e)) => not_saved(e),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete user."),
+    }
+}
+
+async fn delete_sessions(State(state): State<ApiState>, Path(name): Path<String>) -> Response {
+    let result = tokio::task::spawn_blocking(move || {
+        logout_all(&mut state.database.lock().unwrap(), &state.clients, name)
+    })
+    .await;
+
+    match result {
+        Ok(Ok(Some(_))) => StatusCode::NO_CONTENT.into_response(),
+        Ok(Ok(None)) => error(StatusCode::NOT_FOUND, "User not found."),
+        Ok(Err(e)) => not_saved(e),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to end sessions."),
+    }
+}
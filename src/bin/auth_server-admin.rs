@@ -0,0 +1,165 @@
+//! Утилита управления работающим сервером через его сокет управления.
+
+use clap::{Parser, Subcommand};
+use serde_json::{json, Value};
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+#[derive(Parser)]
+#[command(version, about = "Управление пользователями сервера аутентификации")]
+struct Cli {
+    /// Путь к сокету управления сервера.
+    #[arg(long, env = "AUTH_SERVER_CONTROL_SOCKET")]
+    socket: PathBuf,
+    /// Вывести ответ сервера как есть, в формате JSON.
+    #[arg(long)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Выводит список пользователей.
+    List,
+    /// Добавляет пользователя.
+    Add { username: String, password: String },
+    /// Создаёт новую сессию и выводит её токен.
+    Auth { username: String, password: String },
+    /// Завершает сессию по токену, сессию по номеру или все сессии пользователя.
+    Logout { identifier: String, session: Option<String> },
+    /// Удаляет пользователя.
+    Del { username: String },
+    /// Выводит активные сессии пользователя.
+    Sessions { username: String },
+    /// Выводит или назначает роль пользователя: admin, operator или agent.
+    Role { username: String, role: Option<String> },
+    /// Снимает блокировку входа с пользователя или IP-адреса.
+    Unlock { identifier: String },
+    /// Выводит группы пользователя.
+    Groups { username: String },
+    /// Добавляет пользователя в группу.
+    Group { username: String, group: String },
+    /// Исключает пользователя из группы.
+    Ungroup { username: String, group: String },
+    /// Выводит пользователей в сети.
+    Presence,
+    /// Останавливает сервер.
+    Exit,
+}
+
+impl Command {
+    fn to_request(&self) -> Value {
+        match self {
+            Command::List => json!({"command": "list"}),
+            Command::Add { username, password } => json!({"command": "add", "username": username, "password": password}),
+            Command::Auth { username, password } => json!({"command": "auth", "username": username, "password": password}),
+            Command::Logout { identifier, session } => json!({"command": "logout", "identifier": identifier, "session": session}),
+            Command::Del { username } => json!({"command": "del", "username": username}),
+            Command::Sessions { username } => json!({"command": "sessions", "username": username}),
+            Command::Role { username, role } => json!({"command": "role", "username": username, "role": role}),
+            Command::Unlock { identifier } => json!({"command": "unlock", "identifier": identifier}),
+            Command::Groups { username } => json!({"command": "groups", "username": username}),
+            Command::Group { username, group } => json!({"command": "group", "username": username, "group": group}),
+            Command::Ungroup { username, group } => json!({"command": "ungroup", "username": username, "group": group}),
+            Command::Presence => json!({"command": "presence"}),
+            Command::Exit => json!({"command": "exit"}),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn send(socket: &Path, request: &Value) -> std::io::Result<Value> {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        os::unix::net::UnixStream,
+    };
+
+    let mut stream = UnixStream::connect(socket)?;
+    let mut line = serde_json::to_vec(request)?;
+    line.push(b'\n');
+    stream.write_all(&line)?;
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+    Ok(serde_json::from_str(&response)?)
+}
+
+#[cfg(not(unix))]
+fn send(_socket: &Path, _request: &Value) -> std::io::Result<Value> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "сокет управления поддерживается только в Unix-системах",
+    ))
+}
+
+fn print_human(command: &Command, response: &Value) {
+    if let Some(message) = response.get("message").and_then(Value::as_str) {
+        if response.get("status").and_then(Value::as_str) == Some("ok") {
+            println!("{}", message);
+        } else {
+            eprintln!("Ошибка: {}", message);
+        }
+    }
+    let Some(data) = response.get("data") else {
+        return;
+    };
+    match command {
+        Command::List | Command::Groups { .. } => {
+            for name in data.as_array().into_iter().flatten() {
+                println!("{}", name.as_str().unwrap_or_default());
+            }
+        }
+        Command::Auth { .. } => println!("{}", data["token"].as_str().unwrap_or_default()),
+        Command::Role { .. } => println!("{}", data["role"].as_str().unwrap_or_default()),
+        Command::Sessions { .. } => {
+            for session in data.as_array().into_iter().flatten() {
+                println!(
+                    "{} | адрес: {} | устройство: {} | истекает через {} с",
+                    session["id"].as_str().unwrap_or("-"),
+                    session["addr"].as_str().unwrap_or("-"),
+                    session["device"].as_str().unwrap_or("-"),
+                    session["expires_in"],
+                );
+            }
+        }
+        Command::Presence => {
+            for user in data.as_array().into_iter().flatten() {
+                println!(
+                    "{} | роль: {} | соединений: {} | последняя активность: {}",
+                    user["username"].as_str().unwrap_or("-"),
+                    user["role"].as_str().unwrap_or("-"),
+                    user["connections"],
+                    user["last_seen"],
+                );
+            }
+        }
+        _ => println!("{}", data),
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let response = match send(&cli.socket, &cli.command.to_request()) {
+        Ok(response) => response,
+        Err(e) => {
+            eprintln!("Ошибка связи с сервером через '{}': {}", cli.socket.display(), e);
+            return ExitCode::from(2);
+        }
+    };
+
+    if cli.json {
+        println!("{}", response);
+    } else {
+        print_human(&cli.command, &response);
+    }
+
+    if response.get("status").and_then(Value::as_str) == Some("ok") {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
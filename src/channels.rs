@@ -0,0 +1,194 @@
+use serde::Deserialize;
+use std::{cmp::Reverse, collections::HashMap};
+
+use crate::roles::Role;
+
+/// Роли, которым разрешено публиковать в канал и подписываться на него.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelAcl {
+    #[serde(default)]
+    pub publish: Vec<Role>,
+    #[serde(default)]
+    pub subscribe: Vec<Role>,
+}
+
+/// Правила доступа к каналам: шаблон имени канала -> роли. Имя канала состоит
+/// из сегментов через точку. `*` совпадает с одним любым сегментом, а в конце
+/// шаблона — с одним и более сегментами: `alerts.*` покрывает и
+/// `alerts.malware`, и `alerts.malware.critical`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(transparent)]
+pub struct ChannelRules(HashMap<String, ChannelAcl>);
+
+impl Default for ChannelRules {
+    fn default() -> Self {
+        use Role::{Admin, Agent, Operator};
+
+        let rules = [
+            ("alerts.*", ChannelAcl { publish: vec![Admin, Agent], subscribe: vec![Admin, Operator] }),
+            ("updates.*", ChannelAcl { publish: vec![Admin], subscribe: vec![Admin, Operator, Agent] }),
+            ("chat.*", ChannelAcl { publish: vec![Admin, Operator, Agent], subscribe: vec![Admin, Operator, Agent] }),
+        ];
+        ChannelRules(rules.into_iter().map(|(pattern, acl)| (pattern.to_string(), acl)).collect())
+    }
+}
+
+impl ChannelRules {
+    /// Правило для канала или шаблона подписки. Из подходящих правил выбирается
+    /// самое точное: с меньшим числом `*`, при равенстве — с большим числом
+    /// сегментов, затем — с более длинным точным началом до первой `*`.
+    /// Оставшиеся равные правила упорядочиваются по самому шаблону, чтобы выбор
+    /// не зависел от порядка обхода таблицы.
+    fn rule_for(&self, name: &str) -> Option<&ChannelAcl> {
+        self.0.iter()
+            .filter(|(pattern, _)| matches(pattern, name))
+            .min_by_key(|(pattern, _)| {
+                let wildcards: Vec<usize> = pattern.split('.')
+                    .enumerate()
+                    .filter(|(_, s)| *s == "*")
+                    .map(|(i, _)| i)
+                    .collect();
+                (wildcards.len(), Reverse(pattern.split('.').count()), Reverse(wildcards), pattern.as_str())
+            })
+            .map(|(_, acl)| acl)
+    }
+
+    pub fn can_publish(&self, channel: &str, role: Role) -> bool {
+        self.rule_for(channel).is_some_and(|acl| acl.publish.contains(&role))
+    }
+
+    /// `pattern` может содержать `*`: подписка на шаблон разрешена, только если
+    /// правило покрывает сам шаблон, а не отдельные каналы под ним.
+    pub fn can_subscribe(&self, pattern: &str, role: Role) -> bool {
+        self.rule_for(pattern).is_some_and(|acl| acl.subscribe.contains(&role))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for pattern in self.0.keys() {
+            if !is_valid_name(pattern, true) {
+                return Err(format!("некорректный шаблон канала '{}'", pattern));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Проверяет имя канала: непустые сегменты из букв, цифр, `-` и `_`,
+/// а при `wildcards` ещё и сегменты `*`.
+pub fn is_valid_name(name: &str, wildcards: bool) -> bool {
+    name.split('.').all(|segment| {
+        (wildcards && segment == "*")
+            || (!segment.is_empty() && segment.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_'))
+    })
+}
+
+/// Совпадает ли канал `name` с шаблоном `pattern`. Сегмент `*` в `name`
+/// совпадает только с `*` в шаблоне.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('.').collect();
+    let name: Vec<&str> = name.split('.').collect();
+
+    for (i, segment) in pattern.iter().enumerate() {
+        let last = i + 1 == pattern.len();
+        match (*segment, name.get(i)) {
+            (_, None) => return false,
+            ("*", Some(_)) if last => return true,
+            ("*", Some(_)) => {}
+            (segment, Some(other)) if segment == *other => {}
+            _ => return false,
+        }
+    }
+    pattern.len() == name.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(rules: &[(&str, &[Role], &[Role])]) -> ChannelRules {
+        ChannelRules(rules.iter()
+            .map(|(pattern, publish, subscribe)| {
+                (pattern.to_string(), ChannelAcl { publish: publish.to_vec(), subscribe: subscribe.to_vec() })
+            })
+            .collect())
+    }
+
+    #[test]
+    fn matches_exact_and_wildcard_segments() {
+        assert!(matches("alerts.malware", "alerts.malware"));
+        assert!(!matches("alerts.malware", "alerts.virus"));
+        assert!(matches("alerts.*.critical", "alerts.malware.critical"));
+        assert!(!matches("alerts.*.critical", "alerts.malware"));
+        assert!(!matches("alerts.*.critical", "alerts.malware.low"));
+    }
+
+    #[test]
+    fn trailing_wildcard_matches_one_or_more_segments() {
+        assert!(matches("alerts.*", "alerts.malware"));
+        assert!(matches("alerts.*", "alerts.malware.critical"));
+        assert!(!matches("alerts.*", "alerts"));
+        assert!(!matches("alerts.*", "updates.db"));
+    }
+
+    #[test]
+    fn wildcard_in_name_matches_only_wildcard_in_pattern() {
+        assert!(matches("alerts.*", "alerts.*"));
+        assert!(!matches("alerts.malware", "alerts.*"));
+    }
+
+    #[test]
+    fn most_specific_rule_wins() {
+        let rules = rules(&[
+            ("chat.*", &Role::ALL, &Role::ALL),
+            ("chat.admins", &[Role::Admin], &[Role::Admin]),
+        ]);
+        assert!(rules.can_subscribe("chat.general", Role::Agent));
+        assert!(!rules.can_subscribe("chat.admins", Role::Agent));
+        assert!(rules.can_subscribe("chat.admins", Role::Admin));
+        assert!(!rules.can_publish("chat.admins", Role::Operator));
+    }
+
+    #[test]
+    fn wildcard_subscription_does_not_widen_narrower_rule() {
+        let rules = rules(&[
+            ("chat.*", &Role::ALL, &Role::ALL),
+            ("chat.admins", &[Role::Admin], &[Role::Admin]),
+        ]);
+        // Подписка на шаблон разрешена, но доставка в `chat.admins`
+        // проверяется по правилу самого канала.
+        assert!(rules.can_subscribe("chat.*", Role::Agent));
+        assert!(matches("chat.*", "chat.admins"));
+        assert!(!rules.can_subscribe("chat.admins", Role::Agent));
+    }
+
+    #[test]
+    fn equally_specific_rules_prefer_longer_exact_prefix() {
+        // Каждая таблица заново перемешивает порядок обхода `HashMap`.
+        for _ in 0..50 {
+            let rules = rules(&[
+                ("alerts.*.critical", &[Role::Admin], &[Role::Admin]),
+                ("alerts.malware.*", &[Role::Agent], &[Role::Operator]),
+            ]);
+            assert!(rules.can_publish("alerts.malware.critical", Role::Agent));
+            assert!(!rules.can_publish("alerts.malware.critical", Role::Admin));
+            assert!(rules.can_publish("alerts.virus.critical", Role::Admin));
+        }
+    }
+
+    #[test]
+    fn channel_without_rule_is_closed() {
+        let rules = rules(&[("chat.*", &Role::ALL, &Role::ALL)]);
+        assert!(!rules.can_publish("misc", Role::Admin));
+        assert!(!rules.can_subscribe("*", Role::Admin));
+    }
+
+    #[test]
+    fn name_validation() {
+        assert!(is_valid_name("alerts.malware-scan_1", false));
+        assert!(!is_valid_name("alerts.*", false));
+        assert!(is_valid_name("alerts.*", true));
+        assert!(!is_valid_name("alerts..malware", true));
+        assert!(!is_valid_name("", true));
+    }
+}
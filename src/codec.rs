@@ -0,0 +1,163 @@
+use std::{fmt, io};
+use tokio_util::{
+    bytes::BytesMut,
+    codec::{AnyDelimiterCodec, AnyDelimiterCodecError, Decoder, Encoder},
+};
+
+use crate::Message;
+
+/// Кодек протокола: каждый `Message` передаётся одной строкой JSON,
+/// завершённой символом `\n`. Кадры длиннее `max_frame_length` отклоняются.
+/// Строки режутся как байты, поэтому кадр не в UTF-8 — такая же ошибка
+/// разбора JSON, как и любой другой некорректный кадр.
+pub struct MessageCodec {
+    lines: AnyDelimiterCodec,
+}
+
+#[derive(Debug)]
+pub enum CodecError {
+    FrameTooLong,
+    Io(io::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::FrameTooLong => write!(f, "frame exceeds maximum length"),
+            CodecError::Io(e) => write!(f, "{}", e),
+            CodecError::Json(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+impl From<io::Error> for CodecError {
+    fn from(e: io::Error) -> Self {
+        CodecError::Io(e)
+    }
+}
+
+impl From<AnyDelimiterCodecError> for CodecError {
+    fn from(e: AnyDelimiterCodecError) -> Self {
+        match e {
+            AnyDelimiterCodecError::MaxChunkLengthExceeded => CodecError::FrameTooLong,
+            AnyDelimiterCodecError::Io(e) => CodecError::Io(e),
+        }
+    }
+}
+
+impl MessageCodec {
+    pub fn new(max_frame_length: usize) -> Self {
+        MessageCodec {
+            lines: AnyDelimiterCodec::new_with_max_length(b"\n".to_vec(), b"\n".to_vec(), max_frame_length),
+        }
+    }
+}
+
+impl Decoder for MessageCodec {
+    // Ошибка разбора JSON относится к одному кадру и не закрывает поток,
+    // поэтому она возвращается внутри элемента, а не как ошибка декодера.
+    type Item = Result<Message, serde_json::Error>;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        loop {
+            match self.lines.decode(src)? {
+                Some(line) if line.trim_ascii().is_empty() => continue,
+                Some(line) => return Ok(Some(serde_json::from_slice(&line))),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        loop {
+            match self.lines.decode_eof(src)? {
+                Some(line) if line.trim_ascii().is_empty() => continue,
+                Some(line) => return Ok(Some(serde_json::from_slice(&line))),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+impl Encoder<Message> for MessageCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, msg: Message, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let line = serde_json::to_string(&msg).map_err(CodecError::Json)?;
+        self.lines.encode(line, dst)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MessageType;
+
+    fn decode(codec: &mut MessageCodec, buf: &mut BytesMut) -> Option<Message> {
+        codec.decode(buf).unwrap().map(|item| item.unwrap())
+    }
+
+    #[test]
+    fn waits_for_frame_split_across_reads() {
+        let mut codec = MessageCodec::new(1024);
+        let mut buf = BytesMut::from(&br#"{"command":"pi"#[..]);
+        assert!(decode(&mut codec, &mut buf).is_none());
+
+        buf.extend_from_slice(b"ng\",\"data\":null}");
+        assert!(decode(&mut codec, &mut buf).is_none());
+
+        buf.extend_from_slice(b"\n");
+        let msg = decode(&mut codec, &mut buf).unwrap();
+        assert_eq!(msg.command, "ping");
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn decodes_two_frames_from_one_read() {
+        let mut codec = MessageCodec::new(1024);
+        let mut buf = BytesMut::from(&b"{\"command\":\"ping\",\"data\":1}\n\n{\"command\":\"users\",\"data\":2}\n"[..]);
+
+        let first = decode(&mut codec, &mut buf).unwrap();
+        assert_eq!((first.command.as_str(), first.data), ("ping", Some(1.into())));
+        let second = decode(&mut codec, &mut buf).unwrap();
+        assert_eq!((second.command.as_str(), second.data), ("users", Some(2.into())));
+        assert!(decode(&mut codec, &mut buf).is_none());
+    }
+
+    #[test]
+    fn rejects_oversized_frame() {
+        let mut codec = MessageCodec::new(16);
+        let mut buf = BytesMut::from(&br#"{"command":"ping","data":null}"#[..]);
+        assert!(matches!(codec.decode(&mut buf), Err(CodecError::FrameTooLong)));
+    }
+
+    #[test]
+    fn keeps_stream_after_malformed_json() {
+        let mut codec = MessageCodec::new(1024);
+        let mut buf = BytesMut::from(&b"not json\n{\"command\":\"ping\"}\n"[..]);
+        assert!(codec.decode(&mut buf).unwrap().unwrap().is_err());
+        assert_eq!(decode(&mut codec, &mut buf).unwrap().command, "ping");
+    }
+
+    #[test]
+    fn reports_invalid_utf8_as_malformed_frame() {
+        let mut codec = MessageCodec::new(1024);
+        let mut buf = BytesMut::from(&b"\xff\xfe\n{\"command\":\"ping\"}\r\n"[..]);
+        assert!(codec.decode(&mut buf).unwrap().unwrap().is_err());
+        assert_eq!(decode(&mut codec, &mut buf).unwrap().command, "ping");
+    }
+
+    #[test]
+    fn encodes_message_as_one_line() {
+        let mut codec = MessageCodec::new(1024);
+        let mut buf = BytesMut::new();
+        let msg = Message { command: "ping".into(), kind: MessageType::Response, id: None, data: None };
+        codec.encode(msg, &mut buf).unwrap();
+        assert_eq!(&buf[..], b"{\"command\":\"ping\",\"type\":\"response\",\"data\":null}\n");
+    }
+}
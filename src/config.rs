@@ -0,0 +1,293 @@
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+use std::{fmt, fs, io, net::SocketAddr, path::PathBuf, time::Duration};
+
+use crate::{channels::ChannelRules, ratelimit::RateLimits};
+
+#[derive(Deserialize, ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    File,
+    Memory,
+}
+
+/// Настройки сервера. Значения берутся по умолчанию, затем из TOML-файла,
+/// затем из переменных окружения и, наконец, из аргументов командной строки.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Работа без интерактивной консоли на stdin.
+    pub daemon: bool,
+    /// Путь к Unix-сокету локального канала управления.
+    pub control_socket: Option<PathBuf>,
+    /// Права на файл сокета управления, например `0o660` для доступа группе.
+    pub control_socket_mode: u32,
+    pub bind: SocketAddr,
+    /// Адрес HTTP API управления пользователями; если не задан, API выключен.
+    pub http_bind: Option<SocketAddr>,
+    /// Ключ доступа к HTTP API управления.
+    pub admin_api_key: Option<String>,
+    /// Время ожидания завершения клиентских соединений при остановке, в секундах.
+    pub shutdown_timeout: u64,
+    pub max_frame_length: usize,
+    pub outbound_queue_capacity: usize,
+    /// Интервал, с которым клиентам рекомендуется отправлять `"ping"`, в секундах.
+    pub heartbeat_interval: u64,
+    /// Время без единого кадра от клиента, после которого соединение закрывается, в секундах.
+    pub heartbeat_timeout: u64,
+    /// Сколько секунд сообщение ждёт в очереди пользователя, который не в сети.
+    pub outbox_ttl: u64,
+    /// Максимальное число сообщений в очереди одного пользователя.
+    pub outbox_max_depth: usize,
+    /// Ограничения частоты команд протокола; задаются только в файле конфигурации.
+    pub rate_limits: RateLimits,
+    /// Права ролей на каналы публикации; задаются только в файле конфигурации.
+    pub channels: ChannelRules,
+    pub storage: StorageBackend,
+    /// Снимок базы пользователей; журнал изменений хранится рядом с расширением `.journal`.
+    pub database_path: PathBuf,
+    pub secret_key_path: PathBuf,
+    pub token_length: usize,
+    /// Максимальное время жизни токена, в секундах.
+    pub token_lifetime: u64,
+    /// Время бездействия, после которого токен перестаёт действовать, в секундах.
+    pub token_idle_timeout: u64,
+    /// Число неудачных попыток входа подряд до блокировки.
+    pub auth_max_failures: u32,
+    /// Задержка после первой неудачной попытки входа, в секундах; дальше удваивается.
+    pub auth_backoff_base: u64,
+    pub auth_backoff_max: u64,
+    /// Длительность блокировки после `auth_max_failures` неудач, в секундах.
+    pub auth_lockout: u64,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub tls_client_ca: Option<PathBuf>,
+    pub tls_handshake_timeout: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            daemon: false,
+            control_socket: None,
+            control_socket_mode: 0o600,
+            bind: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            http_bind: None,
+            admin_api_key: None,
+            shutdown_timeout: 10,
+            max_frame_length: 64 * 1024,
+            outbound_queue_capacity: 64,
+            heartbeat_interval: 30,
+            heartbeat_timeout: 90,
+            outbox_ttl: 7 * 24 * 60 * 60,
+            outbox_max_depth: 100,
+            rate_limits: RateLimits::default(),
+            channels: ChannelRules::default(),
+            storage: StorageBackend::File,
+            database_path: PathBuf::from("users.json"),
+            secret_key_path: PathBuf::from("server.key"),
+            token_length: 16,
+            token_lifetime: 24 * 60 * 60,
+            token_idle_timeout: 60 * 60,
+            auth_max_failures: 5,
+            auth_backoff_base: 1,
+            auth_backoff_max: 60,
+            auth_lockout: 15 * 60,
+            tls_cert: None,
+            tls_key: None,
+            tls_client_ca: None,
+            tls_handshake_timeout: 10,
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Сервер аутентификации агентов")]
+struct Cli {
+    /// Путь к конфигурационному файлу в формате TOML.
+    #[arg(long, env = "AUTH_SERVER_CONFIG")]
+    config: Option<PathBuf>,
+    /// Запуск без интерактивной консоли.
+    #[arg(long, env = "AUTH_SERVER_DAEMON", num_args = 0..=1, default_missing_value = "true")]
+    daemon: Option<bool>,
+    #[arg(long, env = "AUTH_SERVER_CONTROL_SOCKET")]
+    control_socket: Option<PathBuf>,
+    /// Права на файл сокета управления в восьмеричной записи.
+    #[arg(long, env = "AUTH_SERVER_CONTROL_SOCKET_MODE", value_parser = parse_mode)]
+    control_socket_mode: Option<u32>,
+    #[arg(long, env = "AUTH_SERVER_BIND")]
+    bind: Option<SocketAddr>,
+    #[arg(long, env = "AUTH_SERVER_HTTP_BIND")]
+    http_bind: Option<SocketAddr>,
+    #[arg(long, env = "AUTH_SERVER_ADMIN_API_KEY", hide_env_values = true)]
+    admin_api_key: Option<String>,
+    #[arg(long, env = "AUTH_SERVER_SHUTDOWN_TIMEOUT")]
+    shutdown_timeout: Option<u64>,
+    #[arg(long, env = "AUTH_SERVER_MAX_FRAME_LENGTH")]
+    max_frame_length: Option<usize>,
+    #[arg(long, env = "AUTH_SERVER_OUTBOUND_QUEUE_CAPACITY")]
+    outbound_queue_capacity: Option<usize>,
+    #[arg(long, env = "AUTH_SERVER_HEARTBEAT_INTERVAL")]
+    heartbeat_interval: Option<u64>,
+    #[arg(long, env = "AUTH_SERVER_HEARTBEAT_TIMEOUT")]
+    heartbeat_timeout: Option<u64>,
+    #[arg(long, env = "AUTH_SERVER_OUTBOX_TTL")]
+    outbox_ttl: Option<u64>,
+    #[arg(long, env = "AUTH_SERVER_OUTBOX_MAX_DEPTH")]
+    outbox_max_depth: Option<usize>,
+    #[arg(long, env = "AUTH_SERVER_STORAGE")]
+    storage: Option<StorageBackend>,
+    #[arg(long, env = "AUTH_SERVER_DATABASE_PATH")]
+    database_path: Option<PathBuf>,
+    #[arg(long, env = "AUTH_SERVER_SECRET_KEY_PATH")]
+    secret_key_path: Option<PathBuf>,
+    #[arg(long, env = "AUTH_SERVER_TOKEN_LENGTH")]
+    token_length: Option<usize>,
+    #[arg(long, env = "AUTH_SERVER_TOKEN_LIFETIME")]
+    token_lifetime: Option<u64>,
+    #[arg(long, env = "AUTH_SERVER_TOKEN_IDLE_TIMEOUT")]
+    token_idle_timeout: Option<u64>,
+    #[arg(long, env = "AUTH_SERVER_AUTH_MAX_FAILURES")]
+    auth_max_failures: Option<u32>,
+    #[arg(long, env = "AUTH_SERVER_AUTH_BACKOFF_BASE")]
+    auth_backoff_base: Option<u64>,
+    #[arg(long, env = "AUTH_SERVER_AUTH_BACKOFF_MAX")]
+    auth_backoff_max: Option<u64>,
+    #[arg(long, env = "AUTH_SERVER_AUTH_LOCKOUT")]
+    auth_lockout: Option<u64>,
+    #[arg(long, env = "AUTH_SERVER_TLS_CERT")]
+    tls_cert: Option<PathBuf>,
+    #[arg(long, env = "AUTH_SERVER_TLS_KEY")]
+    tls_key: Option<PathBuf>,
+    #[arg(long, env = "AUTH_SERVER_TLS_CLIENT_CA")]
+    tls_client_ca: Option<PathBuf>,
+    #[arg(long, env = "AUTH_SERVER_TLS_HANDSHAKE_TIMEOUT")]
+    tls_handshake_timeout: Option<u64>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "не удалось прочитать '{}': {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "ошибка в '{}': {}", path.display(), e),
+            ConfigError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn parse_mode(value: &str) -> Result<u32, String> {
+    u32::from_str_radix(value.trim_start_matches("0o"), 8).map_err(|e| e.to_string())
+}
+
+macro_rules! override_fields {
+    ($config:ident, $cli:ident, $($field:ident),* $(,)?) => {
+        $(
+            if let Some(value) = $cli.$field {
+                $config.$field = value;
+            }
+        )*
+    };
+}
+
+impl Config {
+    /// Собирает конфигурацию из файла, окружения и аргументов командной строки.
+    pub fn load() -> Result<Self, ConfigError> {
+        let cli = Cli::parse();
+
+        let mut config = match &cli.config {
+            Some(path) => {
+                let text = fs::read_to_string(path).map_err(|e| ConfigError::Read(path.clone(), e))?;
+                toml::from_str(&text).map_err(|e| ConfigError::Parse(path.clone(), e))?
+            }
+            None => Config::default(),
+        };
+
+        override_fields!(
+            config, cli,
+            daemon, control_socket_mode, bind, shutdown_timeout, max_frame_length, outbound_queue_capacity, storage,
+            heartbeat_interval, heartbeat_timeout, outbox_ttl, outbox_max_depth,
+            database_path, secret_key_path, token_length, token_lifetime, token_idle_timeout,
+            auth_max_failures, auth_backoff_base, auth_backoff_max, auth_lockout, tls_handshake_timeout,
+        );
+        config.control_socket = cli.control_socket.or(config.control_socket);
+        config.http_bind = cli.http_bind.or(config.http_bind);
+        config.admin_api_key = cli.admin_api_key.or(config.admin_api_key);
+        config.tls_cert = cli.tls_cert.or(config.tls_cert);
+        config.tls_key = cli.tls_key.or(config.tls_key);
+        config.tls_client_ca = cli.tls_client_ca.or(config.tls_client_ca);
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: &str| Err(ConfigError::Invalid(message.to_string()));
+
+        if cfg!(not(unix)) && self.control_socket.is_some() {
+            return invalid("control_socket поддерживается только в Unix-системах");
+        }
+        if self.control_socket_mode & !0o777 != 0 || self.control_socket_mode & 0o600 != 0o600 {
+            return invalid("control_socket_mode должен быть в пределах 0o777 и давать владельцу чтение и запись");
+        }
+        if self.http_bind.is_some() && self.admin_api_key.as_ref().is_none_or(|key| key.len() < 16) {
+            return invalid("http_bind требует admin_api_key длиной не меньше 16 символов");
+        }
+        if self.max_frame_length < 1024 {
+            return invalid("max_frame_length должен быть не меньше 1024 байт");
+        }
+        if self.outbound_queue_capacity == 0 {
+            return invalid("outbound_queue_capacity должен быть больше нуля");
+        }
+        if self.heartbeat_interval == 0 || self.heartbeat_timeout <= self.heartbeat_interval {
+            return invalid("heartbeat_interval должен быть больше нуля и меньше heartbeat_timeout");
+        }
+        if self.outbox_ttl == 0 || self.outbox_max_depth == 0 {
+            return invalid("outbox_ttl и outbox_max_depth должны быть больше нуля");
+        }
+        self.rate_limits.validate().map_err(ConfigError::Invalid)?;
+        self.channels.validate().map_err(ConfigError::Invalid)?;
+        if self.token_length < 16 {
+            return invalid("token_length должен быть не меньше 16 символов");
+        }
+        if self.token_lifetime == 0 || self.token_idle_timeout == 0 {
+            return invalid("token_lifetime и token_idle_timeout должны быть больше нуля");
+        }
+        if self.auth_max_failures == 0 || self.auth_lockout == 0 {
+            return invalid("auth_max_failures и auth_lockout должны быть больше нуля");
+        }
+        if self.auth_backoff_base > self.auth_backoff_max {
+            return invalid("auth_backoff_base не может превышать auth_backoff_max");
+        }
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            return invalid("tls_cert и tls_key задаются только вместе");
+        }
+        if self.tls_client_ca.is_some() && self.tls_cert.is_none() {
+            return invalid("tls_client_ca требует tls_cert и tls_key");
+        }
+        if self.tls_handshake_timeout == 0 {
+            return invalid("tls_handshake_timeout должен быть больше нуля");
+        }
+        Ok(())
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout)
+    }
+
+    pub fn heartbeat_timeout(&self) -> Duration {
+        Duration::from_secs(self.heartbeat_timeout)
+    }
+
+    pub fn tls_handshake_timeout(&self) -> Duration {
+        Duration::from_secs(self.tls_handshake_timeout)
+    }
+}
@@ -0,0 +1,252 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    fs, io,
+    os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    add, add_to_group, auth, del, list, logout, logout_session, not_saved, registry::AuthorizedClients,
+    remove_from_group, roles::Role, sessions, set_role, unix_time, unlock, UserDatabase,
+};
+
+/// Запрос канала управления: один JSON-объект на строку.
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase", deny_unknown_fields)]
+enum AdminRequest {
+    List,
+    Add { username: String, password: String },
+    Auth { username: String, password: String },
+    Logout { identifier: String, session: Option<String> },
+    Del { username: String },
+    Sessions { username: String },
+    Role { username: String, role: Option<Role> },
+    Unlock { identifier: String },
+    Groups { username: String },
+    Group { username: String, group: String },
+    Ungroup { username: String, group: String },
+    Presence,
+    Exit,
+}
+
+#[derive(Serialize)]
+struct AdminResponse {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<serde_json::Value>,
+}
+
+impl AdminResponse {
+    fn ok(message: String) -> Self {
+        AdminResponse { status: "ok", message: Some(message), data: None }
+    }
+
+    fn data(data: serde_json::Value) -> Self {
+        AdminResponse { status: "ok", message: None, data: Some(data) }
+    }
+
+    fn err(message: String) -> Self {
+        AdminResponse { status: "err", message: Some(message), data: None }
+    }
+}
+
+/// Создаёт сокет канала управления. Доступ к нему определяется правами
+/// на файл сокета: по умолчанию только владелец процесса.
+///
+/// Оставшийся от прошлого запуска сокет удаляется, только если к нему никто
+/// не принимает подключения; любой другой файл по этому пути — ошибка.
+pub fn bind(path: &Path, mode: u32) -> io::Result<UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("'{}' уже обслуживается другим процессом", path.display()),
+                ));
+            }
+            fs::remove_file(path)?;
+        }
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("'{}' существует и не является сокетом", path.display()),
+            ));
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    // Сокет создаётся в закрытом каталоге рядом и переносится на место уже с
+    // правами `mode`: иначе до `set_permissions` к нему можно подключиться с
+    // правами, которые дал umask.
+    let mut staging = path.as_os_str().to_owned();
+    staging.push(".new");
+    let staging = PathBuf::from(staging);
+    match fs::remove_dir_all(&staging) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    fs::DirBuilder::new().mode(0o700).create(&staging)?;
+    let staged = staging.join("control.sock");
+    let result = UnixListener::bind(&staged).and_then(|listener| {
+        fs::set_permissions(&staged, fs::Permissions::from_mode(mode))?;
+        fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+pub async fn serve(
+    listener: UnixListener,
+    path: PathBuf,
+    database: Arc<Mutex<UserDatabase>>,
+    clients: AuthorizedClients,
+    shutdown: CancellationToken,
+) {
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle_connection(stream, database.clone(), clients.clone(), shutdown.clone()));
+                }
+                Err(e) => eprintln!("Ошибка при подключении к каналу управления: {:?}", e),
+            },
+        }
+    }
+    let _ = fs::remove_file(path);
+}
+
+async fn handle_connection(
+    stream: UnixStream,
+    database: Arc<Mutex<UserDatabase>>,
+    clients: AuthorizedClients,
+    shutdown: CancellationToken,
+) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<AdminRequest>(&line) {
+            Ok(request) => {
+                let database = database.clone();
+                let clients = clients.clone();
+                let shutdown = shutdown.clone();
+                // Команды хэшируют пароли, поэтому выполняются вне рабочих потоков runtime.
+                tokio::task::spawn_blocking(move || execute(&database, &clients, request, &shutdown))
+                    .await
+                    .unwrap_or_else(|_| AdminResponse::err("Ошибка выполнения команды.".to_string()))
+            }
+            Err(e) => AdminResponse::err(format!("Некорректный запрос: {}", e)),
+        };
+
+        let Ok(mut reply) = serde_json::to_vec(&response) else {
+            return;
+        };
+        reply.push(b'\n');
+        if writer.write_all(&reply).await.is_err() {
+            return;
+        }
+    }
+}
+
+// База блокируется на время одной команды; `add` и `auth` хэшируют пароль без блокировки.
+fn execute(
+    database: &Mutex<UserDatabase>,
+    clients: &AuthorizedClients,
+    request: AdminRequest,
+    shutdown: &CancellationToken,
+) -> AdminResponse {
+    let db = || database.lock().unwrap();
+    match request {
+        AdminRequest::Presence => AdminResponse::data(json!(clients.presence())),
+        AdminRequest::List => AdminResponse::data(json!(list(&db()).unwrap_or_default())),
+        AdminRequest::Add { username, password } => match add(database, username, password) {
+            Ok(Some(message)) => AdminResponse::ok(message),
+            Ok(None) => AdminResponse::err("Пользователь с таким именем уже существует.".to_string()),
+            Err(e) => AdminResponse::err(not_saved(&e)),
+        },
+        AdminRequest::Auth { username, password } => match auth(database, username, password, None, None) {
+            Ok(Some(token)) => AdminResponse::data(json!({"token": token})),
+            Ok(None) => AdminResponse::err("Ошибка аутентификации.".to_string()),
+            Err(e) => AdminResponse::err(not_saved(&e)),
+        },
+        AdminRequest::Logout { identifier, session: None } => match logout(&mut db(), clients, identifier) {
+            Ok(Some(message)) => AdminResponse::ok(message),
+            Ok(None) => AdminResponse::err("Пользователь или токен не найден.".to_string()),
+            Err(e) => AdminResponse::err(not_saved(&e)),
+        },
+        AdminRequest::Logout { identifier, session: Some(session_id) } => {
+            match logout_session(&mut db(), clients, identifier.clone(), session_id.clone()) {
+                Ok(Some(message)) => AdminResponse::ok(message),
+                Ok(None) => AdminResponse::err(format!("Сессия '{}' пользователя '{}' не найдена.", session_id, identifier)),
+                Err(e) => AdminResponse::err(not_saved(&e)),
+            }
+        }
+        AdminRequest::Del { username } => match del(&mut db(), clients, username.clone()) {
+            Ok(Some(message)) => AdminResponse::ok(message),
+            Ok(None) => AdminResponse::err(format!("Пользователь '{}' не найден.", username)),
+            Err(e) => AdminResponse::err(not_saved(&e)),
+        },
+        AdminRequest::Sessions { username } => match sessions(&db(), username.clone()) {
+            Some(sessions) => {
+                let now = unix_time();
+                AdminResponse::data(json!(sessions.iter().map(|s| json!({
+                    "id": s.id,
+                    "addr": s.addr,
+                    "device": s.device,
+                    "issued_at": s.issued_at,
+                    "last_used": s.last_used,
+                    "expires_at": s.expires_at,
+                    "expires_in": s.expires_at.saturating_sub(now),
+                })).collect::<Vec<_>>()))
+            }
+            None => AdminResponse::err(format!("Пользователь '{}' не найден.", username)),
+        },
+        AdminRequest::Role { username, role: None } => match db().users.get(&username) {
+            Some(user) => AdminResponse::data(json!({"role": user.role})),
+            None => AdminResponse::err(format!("Пользователь '{}' не найден.", username)),
+        },
+        AdminRequest::Role { username, role: Some(role) } => match set_role(&mut db(), clients, username.clone(), role) {
+            Ok(Some(message)) => AdminResponse::ok(message),
+            Ok(None) => AdminResponse::err(format!("Пользователь '{}' не найден.", username)),
+            Err(e) => AdminResponse::err(not_saved(&e)),
+        },
+        AdminRequest::Unlock { identifier } => match unlock(&mut db(), identifier.clone()) {
+            Some(message) => AdminResponse::ok(message),
+            None => AdminResponse::err(format!("'{}' не заблокирован.", identifier)),
+        },
+        AdminRequest::Groups { username } => match db().users.get(&username) {
+            Some(user) => AdminResponse::data(json!(user.groups)),
+            None => AdminResponse::err(format!("Пользователь '{}' не найден.", username)),
+        },
+        AdminRequest::Group { username, group } => match add_to_group(&mut db(), clients, username.clone(), group) {
+            Ok(Some(message)) => AdminResponse::ok(message),
+            Ok(None) => AdminResponse::err(format!("Пользователь '{}' не найден или уже состоит в группе.", username)),
+            Err(e) => AdminResponse::err(not_saved(&e)),
+        },
+        AdminRequest::Ungroup { username, group } => match remove_from_group(&mut db(), clients, username.clone(), group) {
+            Ok(Some(message)) => AdminResponse::ok(message),
+            Ok(None) => AdminResponse::err(format!("Пользователь '{}' не найден или не состоит в группе.", username)),
+            Err(e) => AdminResponse::err(not_saved(&e)),
+        },
+        AdminRequest::Exit => {
+            shutdown.cancel();
+            AdminResponse::ok("Сервер останавливается.".to_string())
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use serde_json::{json, Map, Value};
+
+use crate::{Message, MessageType};
+
+/// Машиночитаемый код ошибки протокола.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    InvalidJson,
+    FrameTooLong,
+    InvalidRequest,
+    InvalidCredentials,
+    InvalidToken,
+    PermissionDenied,
+    UnknownRecipient,
+    RecipientOffline,
+    OutboxFull,
+    RateLimited,
+    Locked,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::InvalidJson => "invalid_json",
+            ErrorCode::FrameTooLong => "frame_too_long",
+            ErrorCode::InvalidRequest => "invalid_request",
+            ErrorCode::InvalidCredentials => "invalid_credentials",
+            ErrorCode::InvalidToken => "invalid_token",
+            ErrorCode::PermissionDenied => "permission_denied",
+            ErrorCode::UnknownRecipient => "unknown_recipient",
+            ErrorCode::RecipientOffline => "recipient_offline",
+            ErrorCode::OutboxFull => "outbox_full",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::Locked => "locked",
+            ErrorCode::Internal => "internal_error",
+        }
+    }
+
+    // Блокировка и превышение лимита имеют собственный статус, чтобы клиент
+    // отличал их от обычной ошибки без разбора кода.
+    fn status(self) -> &'static str {
+        match self {
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::Locked => "locked",
+            _ => "err",
+        }
+    }
+}
+
+/// Ошибка обработки запроса клиента. Отправляется клиенту в едином формате:
+/// `{"status", "code", "message", ...}` в поле `data` ответа.
+#[derive(Debug)]
+pub struct ProtocolError {
+    code: ErrorCode,
+    message: String,
+    details: Map<String, Value>,
+}
+
+impl ProtocolError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        ProtocolError { code, message: message.into(), details: Map::new() }
+    }
+
+    /// Добавляет в ответ дополнительное поле, например `retry_after`.
+    pub fn with(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.details.insert(key.to_string(), value.into());
+        self
+    }
+
+    /// Превращает ошибку в ответ на команду `command`, запрос с `id` получает его обратно.
+    pub fn into_message(self, command: &str, id: Option<Value>) -> Message {
+        let mut data = self.details;
+        data.insert("status".to_string(), json!(self.code.status()));
+        data.insert("code".to_string(), json!(self.code.as_str()));
+        data.insert("message".to_string(), json!(self.message));
+        Message {
+            command: command.to_string(),
+            kind: MessageType::Response,
+            id,
+            data: Some(Value::Object(data)),
+        }
+    }
+}
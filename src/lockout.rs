@@ -0,0 +1,219 @@
+use std::{collections::HashMap, net::IpAddr};
+
+pub struct LockoutPolicy {
+    /// Число неудачных попыток подряд, после которого вход блокируется на `lockout`.
+    pub max_failures: u32,
+    /// Задержка после первой неудачи; каждая следующая удваивает её до `backoff_max`.
+    pub backoff_base: u64,
+    pub backoff_max: u64,
+    pub lockout: u64,
+}
+
+struct Failures {
+    count: u32,
+    /// Попытки, которые прошли `check`, но ещё не завершились.
+    in_flight: u32,
+    last_failure: u64,
+    blocked_until: u64,
+}
+
+/// Счётчики неудачных попыток входа по учётным записям и по адресам клиентов.
+/// Хранятся только в памяти: после перезапуска сервера блокировки снимаются.
+pub struct LoginGuard {
+    policy: LockoutPolicy,
+    accounts: HashMap<String, Failures>,
+    addrs: HashMap<IpAddr, Failures>,
+}
+
+impl LoginGuard {
+    pub fn new(policy: LockoutPolicy) -> Self {
+        LoginGuard { policy, accounts: HashMap::new(), addrs: HashMap::new() }
+    }
+
+    /// Резервирует попытку входа до проверки пароля, чтобы параллельные
+    /// попытки не обходили `max_failures`. Результат попытки передаётся в
+    /// `record_success` или `record_failure`. Возвращает `Err` с числом секунд
+    /// до следующей разрешённой попытки.
+    pub fn check(&mut self, username: &str, known_user: bool, ip: IpAddr, now: u64) -> Result<(), u64> {
+        self.forget_expired(now);
+        let retry_after = [self.accounts.get(username), self.addrs.get(&ip)]
+            .into_iter()
+            .flatten()
+            .filter_map(|f| f.retry_after(&self.policy, now))
+            .max();
+        if let Some(retry_after) = retry_after {
+            return Err(retry_after);
+        }
+
+        if known_user {
+            self.accounts.entry(username.to_string()).or_insert_with(Failures::new).in_flight += 1;
+        }
+        self.addrs.entry(ip).or_insert_with(Failures::new).in_flight += 1;
+        Ok(())
+    }
+
+    /// Учитывает неудачную попытку. Счётчик учётной записи ведётся только для
+    /// существующих пользователей, чтобы перебор имён не раздувал таблицу.
+    pub fn record_failure(&mut self, username: &str, known_user: bool, ip: IpAddr, now: u64) {
+        self.finish(username, ip);
+        if known_user {
+            let failures = self.accounts.entry(username.to_string()).or_insert_with(Failures::new);
+            failures.record(&self.policy, now);
+        }
+        self.addrs.entry(ip).or_insert_with(Failures::new).record(&self.policy, now);
+    }
+
+    /// Сбрасывает только счётчик учётной записи: иначе владелец любой учётной
+    /// записи мог бы обнулять счётчик своего адреса между попытками перебора.
+    pub fn record_success(&mut self, username: &str, ip: IpAddr) {
+        self.finish(username, ip);
+        self.accounts.remove(username);
+    }
+
+    // Снимает резерв, сделанный в `check`.
+    fn finish(&mut self, username: &str, ip: IpAddr) {
+        for failures in [self.accounts.get_mut(username), self.addrs.get_mut(&ip)].into_iter().flatten() {
+            failures.in_flight = failures.in_flight.saturating_sub(1);
+        }
+    }
+
+    fn forget_expired(&mut self, now: u64) {
+        let reset_after = self.policy.lockout;
+        let keep = |f: &mut Failures| {
+            if now >= f.last_failure + reset_after {
+                f.count = 0;
+            }
+            f.count > 0 || f.in_flight > 0
+        };
+        self.accounts.retain(|_, f| keep(f));
+        self.addrs.retain(|_, f| keep(f));
+    }
+
+    pub fn unlock_account(&mut self, username: &str) -> bool {
+        self.accounts.remove(username).is_some()
+    }
+
+    pub fn unlock_addr(&mut self, ip: &IpAddr) -> bool {
+        self.addrs.remove(ip).is_some()
+    }
+}
+
+impl Failures {
+    fn new() -> Self {
+        Failures { count: 0, in_flight: 0, last_failure: 0, blocked_until: 0 }
+    }
+
+    // Незавершённые попытки считаются неудачными заранее: пока их результат
+    // неизвестен, новые попытки сверх `max_failures` не разрешаются.
+    fn retry_after(&self, policy: &LockoutPolicy, now: u64) -> Option<u64> {
+        if self.blocked_until > now {
+            Some(self.blocked_until - now)
+        } else if self.count + self.in_flight >= policy.max_failures {
+            Some(1)
+        } else {
+            None
+        }
+    }
+
+    fn record(&mut self, policy: &LockoutPolicy, now: u64) {
+        self.count += 1;
+        self.last_failure = now;
+        let delay = if self.count >= policy.max_failures {
+            policy.lockout
+        } else {
+            let factor = 1u64.checked_shl(self.count - 1).unwrap_or(u64::MAX);
+            policy.backoff_base.saturating_mul(factor).min(policy.backoff_max)
+        };
+        self.blocked_until = now + delay;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+    const OTHER_IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 1));
+
+    fn guard() -> LoginGuard {
+        LoginGuard::new(LockoutPolicy { max_failures: 4, backoff_base: 2, backoff_max: 5, lockout: 100 })
+    }
+
+    fn fail(guard: &mut LoginGuard, ip: IpAddr, now: u64) {
+        guard.check("alice", true, ip, now).unwrap();
+        guard.record_failure("alice", true, ip, now);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max_then_locks_out() {
+        let mut guard = guard();
+        let mut now = 1000;
+        for expected in [2, 4, 5] {
+            fail(&mut guard, IP, now);
+            assert_eq!(guard.check("alice", true, OTHER_IP, now), Err(expected));
+            now += expected;
+        }
+        fail(&mut guard, IP, now);
+        assert_eq!(guard.check("alice", true, OTHER_IP, now), Err(100));
+        assert_eq!(guard.check("alice", true, OTHER_IP, now + 99), Err(1));
+        assert_eq!(guard.check("alice", true, OTHER_IP, now + 100), Ok(()));
+    }
+
+    #[test]
+    fn parallel_attempts_cannot_exceed_max_failures() {
+        let mut guard = guard();
+        for _ in 0..4 {
+            guard.check("alice", true, IP, 1000).unwrap();
+        }
+        assert!(guard.check("alice", true, IP, 1000).is_err());
+        assert!(guard.check("alice", true, OTHER_IP, 1000).is_err());
+
+        guard.record_success("alice", IP);
+        assert_eq!(guard.check("alice", true, OTHER_IP, 1000), Ok(()));
+    }
+
+    #[test]
+    fn success_resets_account_but_not_address() {
+        let mut guard = guard();
+        fail(&mut guard, IP, 1000);
+        fail(&mut guard, IP, 1002);
+        guard.check("alice", true, IP, 1010).unwrap();
+        guard.record_success("alice", IP);
+
+        assert_eq!(guard.check("bob", true, OTHER_IP, 1010), Ok(()));
+        guard.record_success("bob", OTHER_IP);
+        fail(&mut guard, IP, 1010);
+        assert_eq!(guard.check("bob", true, IP, 1010), Err(5));
+    }
+
+    #[test]
+    fn unknown_users_are_counted_by_address_only() {
+        let mut guard = guard();
+        guard.check("ghost", false, IP, 1000).unwrap();
+        guard.record_failure("ghost", false, IP, 1000);
+        assert!(guard.accounts.is_empty());
+        assert_eq!(guard.check("ghost", false, IP, 1000), Err(2));
+    }
+
+    #[test]
+    fn failures_expire_after_lockout_period() {
+        let mut guard = guard();
+        fail(&mut guard, IP, 1000);
+        fail(&mut guard, IP, 1002);
+        guard.check("alice", true, IP, 1102).unwrap();
+        guard.record_failure("alice", true, IP, 1102);
+        assert_eq!(guard.check("alice", true, IP, 1102), Err(2));
+    }
+
+    #[test]
+    fn unlock_clears_blocks() {
+        let mut guard = guard();
+        for now in [1000, 1002, 1006, 1011] {
+            fail(&mut guard, IP, now);
+        }
+        assert!(guard.unlock_account("alice"));
+        assert!(guard.check("alice", true, OTHER_IP, 1011).is_ok());
+        assert!(guard.unlock_addr(&IP));
+        assert!(!guard.unlock_addr(&IP));
+    }
+}
@@ -1,32 +1,144 @@
+mod admin_api;
+mod channels;
+mod codec;
+mod config;
+#[cfg(unix)]
+mod control;
+mod error;
+mod lockout;
+mod outbox;
+mod password;
+mod protocol;
+mod ratelimit;
+mod registry;
+mod roles;
+mod storage;
+mod tls;
+mod token;
+
+use codec::{CodecError, MessageCodec};
+use config::{Config, StorageBackend};
+use error::{ErrorCode, ProtocolError};
+use protocol::{
+    AckRequest, Acknowledged, AuthRequest, Broadcast, ChannelRequest, Delivery, Heartbeat, LogoutRequest, MessageRequest, Notice,
+    OnlineUsers, Publication, PublishRequest, Recipient, Request, Response, Status, TokenRequest,
+};
+use lockout::{LockoutPolicy, LoginGuard};
+use outbox::Outbox;
+use ratelimit::{ConnectionLimits, RateLimiter};
+use registry::{AuthorizedClients, ConnectionHandle, Outbound, Subscriptions};
+use roles::{Permission, Role};
+use storage::{FileStore, MemoryStore, UserStore};
+use tls::TlsSettings;
+use token::TokenHasher;
+use futures::{SinkExt, StreamExt};
 use rand::{self, thread_rng, Rng};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 use std::{
     io::{self, Write},
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
     collections::HashMap,
-    thread
+    thread,
+    time::{SystemTime, UNIX_EPOCH}
 };
 use tokio::{
-    time::{Duration, timeout},
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpListener, TcpStream}
+    task::block_in_place,
+    time::{sleep_until, timeout, Instant},
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    sync::mpsc
+};
+use tokio_util::{
+    codec::{FramedRead, FramedWrite},
+    sync::CancellationToken,
+    task::TaskTracker
 };
 
-type AuthorizedClients = Arc<Mutex<HashMap<String, TcpStream>>>;
+const SESSION_ID_LENGTH: usize = 8;
 
-struct User {
-    password: String,
-    token: Option<String>,
+#[derive(Serialize, Deserialize)]
+pub struct User {
+    password_hash: String,
+    #[serde(default)]
+    role: Role,
+    /// Группы, по которым пользователю можно адресовать сообщения.
+    #[serde(default)]
+    groups: Vec<String>,
+    #[serde(default)]
+    sessions: Vec<Session>,
+    #[serde(default, skip_serializing_if = "Outbox::is_unused")]
+    outbox: Outbox,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Session {
+    id: String,
+    token_hash: String,
+    issued_at: u64,
+    expires_at: u64,
+    last_used: u64,
+    addr: Option<String>,
+    device: Option<String>,
+}
+
+impl Session {
+    fn issue(token_hash: String, policy: &TokenPolicy, addr: Option<String>, device: Option<String>) -> Self {
+        let now = unix_time();
+        Session {
+            id: random_string(SESSION_ID_LENGTH),
+            token_hash,
+            issued_at: now,
+            expires_at: now + policy.lifetime,
+            last_used: now,
+            addr,
+            device,
+        }
+    }
+
+    fn is_valid(&self, now: u64, policy: &TokenPolicy) -> bool {
+        now < self.expires_at && now < self.last_used + policy.idle_timeout
+    }
+}
+
+struct TokenPolicy {
+    length: usize,
+    lifetime: u64,
+    idle_timeout: u64,
 }
 
 struct UserDatabase {
     users: HashMap<String, User>,
+    // Индекс токенов: HMAC от токена -> имя пользователя. Поиск идёт по
+    // хэшу, поэтому время сравнения не зависит от совпадающего префикса токена.
+    tokens: HashMap<String, String>,
+    token_hasher: TokenHasher,
+    token_policy: TokenPolicy,
+    login_guard: LoginGuard,
+    store: Box<dyn UserStore>,
+}
+
+/// Вид сообщения: запрос клиента, ответ сервера на запрос или событие,
+/// которое сервер отправляет по своей инициативе (рассылки, остановка сервера).
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageType {
+    #[default]
+    Request,
+    Response,
+    Event,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-struct Message {
+pub struct Message {
     command: String,
+    #[serde(rename = "type", default)]
+    kind: MessageType,
+    /// Идентификатор запроса, выбранный клиентом; возвращается в ответе на этот запрос.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<serde_json::Value>,
     data: Option<serde_json::Value>,
 }
 
@@ -34,25 +146,116 @@ impl Clone for Message {
     fn clone(&self) -> Self {
         Message {
             command: self.command.clone(),
+            kind: self.kind,
+            id: self.id.clone(),
             data: self.data.clone(),
         }
     }
 }
 
 impl UserDatabase {
-    fn new() -> Self {
-        UserDatabase {
-            users: HashMap::new(),
-        }
+    fn open(
+        mut store: Box<dyn UserStore>,
+        token_hasher: TokenHasher,
+        token_policy: TokenPolicy,
+        login_guard: LoginGuard,
+    ) -> io::Result<Self> {
+        let users = store.load()?;
+        let tokens = users.iter()
+            .flat_map(|(username, user)| {
+                user.sessions.iter().map(move |s| (s.token_hash.clone(), username.clone()))
+            })
+            .collect();
+        Ok(UserDatabase { users, tokens, token_hasher, token_policy, login_guard, store })
+    }
+
+    // Сохраняет изменения пользователя `username`, в том числе его удаление.
+    fn persist(&mut self, username: &str) -> io::Result<()> {
+        self.store.save(&self.users, username)
+    }
+
+    fn snapshot(&mut self) -> io::Result<()> {
+        self.store.snapshot(&self.users)
     }
 
     fn find_user_by_token(&self, token: &str) -> Option<String> {
-        self.users.iter()
-            .find(|(_, user)| user.token.as_deref() == Some(token))
-            .map(|(username, _)| username.clone())
+        let token_hash = self.token_hasher.hash(token);
+        let username = self.tokens.get(&token_hash)?;
+        let now = unix_time();
+        self.users.get(username)?
+            .sessions.iter()
+            .any(|s| s.token_hash == token_hash && s.is_valid(now, &self.token_policy))
+            .then(|| username.clone())
+    }
+
+    fn find_session_mut(&mut self, token: &str) -> Option<(String, &mut Session)> {
+        let token_hash = self.token_hasher.hash(token);
+        let username = self.tokens.get(&token_hash)?;
+        let now = unix_time();
+        let policy = &self.token_policy;
+        let session = self.users.get_mut(username)?
+            .sessions.iter_mut()
+            .find(|s| s.token_hash == token_hash && s.is_valid(now, policy))?;
+        Some((username.clone(), session))
+    }
+
+    // Создаёт сессию и возвращает её токен. Сам токен нигде не сохраняется.
+    fn issue_session(&mut self, username: &str, addr: Option<String>, device: Option<String>) -> Option<String> {
+        let user = self.users.get_mut(username)?;
+        let token = generate_token(self.token_policy.length);
+        let token_hash = self.token_hasher.hash(&token);
+        self.tokens.insert(token_hash.clone(), username.to_string());
+        user.sessions.push(Session::issue(token_hash, &self.token_policy, addr, device));
+        Some(token)
+    }
+
+    // Удаляет сессии пользователя, для которых `keep` вернул `false`, вместе с их
+    // записями в индексе. Возвращает номера удалённых сессий.
+    fn retain_sessions(&mut self, username: &str, keep: impl Fn(&Session) -> bool) -> Vec<String> {
+        let mut removed = Vec::new();
+        if let Some(user) = self.users.get_mut(username) {
+            let tokens = &mut self.tokens;
+            user.sessions.retain(|s| {
+                let retained = keep(s);
+                if !retained {
+                    tokens.remove(&s.token_hash);
+                    removed.push(s.id.clone());
+                }
+                retained
+            });
+        }
+        removed
+    }
+
+    fn prune_expired(&mut self, username: &str, now: u64) {
+        if let Some(user) = self.users.get_mut(username) {
+            let (tokens, policy) = (&mut self.tokens, &self.token_policy);
+            user.sessions.retain(|s| {
+                let valid = s.is_valid(now, policy);
+                if !valid {
+                    tokens.remove(&s.token_hash);
+                }
+                valid
+            });
+        }
+    }
+
+    // Продлевает сессию при каждом использовании токена. Время последнего
+    // использования не сохраняется на диск, чтобы не писать файл на каждое сообщение.
+    fn touch_token(&mut self, token: &str) -> Option<String> {
+        let (username, session) = self.find_session_mut(token)?;
+        session.last_used = unix_time();
+        Some(username)
     }
 }
 
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
 fn list(users: &UserDatabase) -> Option<Vec<String>> {
     if users.users.is_empty() {
         None
@@ -61,149 +264,618 @@ fn list(users: &UserDatabase) -> Option<Vec<String>> {
     }
 }
 
-fn add(users: &mut UserDatabase, username: String, password: String) -> Option<String> {
+// Пароль хэшируется без блокировки базы: Argon2 занимает заметное время.
+fn add(database: &Mutex<UserDatabase>, username: String, password: String) -> io::Result<Option<String>> {
+    if database.lock().unwrap().users.contains_key(&username) {
+        return Ok(None);
+    }
+    let password_hash = password::hash_password(&password);
+    insert_user(&mut database.lock().unwrap(), username, password_hash)
+}
+
+// Существующий пользователь не перезаписывается: это сбросило бы его роль,
+// группы и очередь сообщений.
+fn insert_user(users: &mut UserDatabase, username: String, password_hash: String) -> io::Result<Option<String>> {
     if users.users.contains_key(&username) {
-        if let Some(existing_user) = users.users.get(&username) {
-            if existing_user.password == password {
-                return None;
-            }
-        }
+        return Ok(None);
     }
 
     let user = User {
-        password,
-        token: None,
+        password_hash,
+        role: Role::default(),
+        groups: Vec::new(),
+        sessions: Vec::new(),
+        outbox: Outbox::default(),
     };
     users.users.insert(username.clone(), user);
-    Some(format!("Пользователь '{}' добавлен.", username))
+    users.persist(&username)?;
+    Ok(Some(format!("Пользователь '{}' добавлен.", username)))
 }
 
-fn generate_token() -> String {
+fn random_string(length: usize) -> String {
     let mut rng = thread_rng();
-    (0..16)
+    (0..length)
         .map(|_| rng.sample(rand::distributions::Alphanumeric))
         .map(char::from)
         .collect()
 }
 
-fn auth(database: &mut UserDatabase, username: String, password: String) -> Option<String> {
-    if let Some(user) = database.users.get_mut(&username) {
-        if password == user.password {
-            let token = generate_token();
-            user.token = Some(token.clone());
-            return Some(token);
-        }
+fn generate_token(length: usize) -> String {
+    random_string(length)
+}
+
+// Проверяет пароль; базу при этом блокировать не нужно. Возвращает `None` при
+// неверном пароле или неизвестном пользователе, иначе — новый хэш, если старый
+// стоит пересчитать.
+fn verify_credentials(password: &str, password_hash: Option<&str>) -> Option<Option<String>> {
+    if !password::verify_password_or_dummy(password, password_hash) {
+        return None;
     }
-    None
+    Some(password_hash.is_some_and(password::needs_rehash).then(|| password::hash_password(password)))
 }
 
-fn logout(database: &mut UserDatabase, identifier: String) -> Option<String> {
-    if let Some(username) = database.find_user_by_token(&identifier) {
-        if let Some(user) = database.users.get_mut(&username) {
-            user.token = None;
-            return Some(format!("Пользователь '{}' разлогинен.", username));
-        }
+// Создаёт сессию после проверки пароля хэшем `checked_hash`. Если хэш
+// пользователя успел смениться, вход отклоняется.
+fn start_session(
+    database: &mut UserDatabase,
+    username: &str,
+    checked_hash: &str,
+    rehash: Option<String>,
+    addr: Option<String>,
+    device: Option<String>,
+) -> io::Result<Option<String>> {
+    let Some(user) = database.users.get_mut(username) else {
+        return Ok(None);
+    };
+    if user.password_hash != checked_hash {
+        return Ok(None);
+    }
+    if let Some(password_hash) = rehash {
+        user.password_hash = password_hash;
     }
 
-    if let Some(user) = database.users.get_mut(&identifier) {
-        user.token = None;
-        return Some(format!("Пользователь '{}' разлогинен.", identifier));
+    let now = unix_time();
+    database.prune_expired(username, now);
+    let token = database.issue_session(username, addr, device);
+    database.persist(username)?;
+    Ok(token)
+}
+
+// Как и `add`, проверяет пароль без блокировки базы.
+fn auth(
+    database: &Mutex<UserDatabase>,
+    username: String,
+    password: String,
+    addr: Option<String>,
+    device: Option<String>,
+) -> io::Result<Option<String>> {
+    let password_hash = database.lock().unwrap().users.get(&username).map(|user| user.password_hash.clone());
+    let (Some(rehash), Some(password_hash)) = (verify_credentials(&password, password_hash.as_deref()), password_hash) else {
+        return Ok(None);
+    };
+    start_session(&mut database.lock().unwrap(), &username, &password_hash, rehash, addr, device)
+}
+
+// Соединения, аутентифицированные обновлённым токеном, переходят на новую сессию.
+fn refresh(database: &mut UserDatabase, clients: &AuthorizedClients, token: &str) -> io::Result<Option<String>> {
+    let Some((username, session)) = database.find_session_mut(token) else {
+        return Ok(None);
+    };
+    let old = session.clone();
+    database.retain_sessions(&username, |s| s.id != old.id);
+    let fresh_token = database.issue_session(&username, old.addr, old.device);
+    if let Some(fresh) = database.users.get_mut(&username).and_then(|user| user.sessions.last_mut()) {
+        // Меняется только секрет: срок жизни сессии по-прежнему отсчитывается от входа.
+        fresh.issued_at = old.issued_at;
+        fresh.expires_at = old.expires_at;
+        clients.replace_session(&username, &old.id, &fresh.id);
+    }
+    database.persist(&username)?;
+    Ok(fresh_token)
+}
+
+// Соединения завершённых сессий закрываются, чтобы они больше не получали сообщений.
+fn logout(database: &mut UserDatabase, clients: &AuthorizedClients, identifier: String) -> io::Result<Option<String>> {
+    if let Some((username, session)) = database.find_session_mut(&identifier) {
+        let id = session.id.clone();
+        let removed = database.retain_sessions(&username, |s| s.id != id);
+        clients.close_sessions(&username, &removed, "Session ended.");
+        database.persist(&username)?;
+        return Ok(Some(format!("Сессия пользователя '{}' завершена.", username)));
     }
 
-    None
+    logout_all(database, clients, identifier)
 }
 
-fn del(database: &mut UserDatabase, username: String) -> Option<String> {
-    if database.users.remove(&username).is_some() {
-        Some(format!("Пользователь '{}' удален.", username))
-    } else {
-        None
+fn logout_all(database: &mut UserDatabase, clients: &AuthorizedClients, username: String) -> io::Result<Option<String>> {
+    if !database.users.contains_key(&username) {
+        return Ok(None);
     }
+    let removed = database.retain_sessions(&username, |_| false);
+    clients.close_sessions(&username, &removed, "Session ended.");
+    database.persist(&username)?;
+    Ok(Some(format!("Пользователь '{}' разлогинен.", username)))
 }
 
-fn get_token(database: &UserDatabase, username: String) -> Option<String> {
-    if let Some(user) = database.users.get(&username) {
-        if let Some(token) = &user.token {
-            return Some(token.to_string());
-        }
+fn logout_session(
+    database: &mut UserDatabase,
+    clients: &AuthorizedClients,
+    username: String,
+    session_id: String,
+) -> io::Result<Option<String>> {
+    let exists = database.users.get(&username).is_some_and(|user| user.sessions.iter().any(|s| s.id == session_id));
+    if !exists {
+        return Ok(None);
     }
-    None
+    let removed = database.retain_sessions(&username, |s| s.id != session_id);
+    clients.close_sessions(&username, &removed, "Session ended.");
+    database.persist(&username)?;
+    Ok(Some(format!("Сессия '{}' пользователя '{}' завершена.", session_id, username)))
+}
+
+fn sessions(database: &UserDatabase, username: String) -> Option<Vec<&Session>> {
+    let user = database.users.get(&username)?;
+    let now = unix_time();
+    Some(user.sessions.iter()
+        .filter(|s| s.is_valid(now, &database.token_policy))
+        .collect())
 }
 
-fn auth_user(database: Arc<Mutex<UserDatabase>>, msg: Message) -> Result<Message, Message> {
-    let data = match msg.data {
-        Some(serde_json::Value::Object(map)) => map,
-        _ => return Err(Message {
-            command: "auth".to_string(),
-            data: Some(serde_json::json!({"status": "err", "message": "Expected JSON object in 'data' field."})),
-        }),
+fn describe_session(session: &Session, now: u64) -> String {
+    format!(
+        "{} | адрес: {} | устройство: {} | создана {} с назад, активна {} с назад, истекает через {} с",
+        session.id,
+        session.addr.as_deref().unwrap_or("-"),
+        session.device.as_deref().unwrap_or("-"),
+        now.saturating_sub(session.issued_at),
+        now.saturating_sub(session.last_used),
+        session.expires_at.saturating_sub(now),
+    )
+}
+
+// Роль и группы сразу применяются к открытым соединениям пользователя.
+fn set_role(
+    database: &mut UserDatabase,
+    clients: &AuthorizedClients,
+    username: String,
+    role: Role,
+) -> io::Result<Option<String>> {
+    let Some(user) = database.users.get_mut(&username) else {
+        return Ok(None);
     };
+    user.role = role;
+    clients.update_role(&username, role);
+    database.persist(&username)?;
+    Ok(Some(format!("Пользователю '{}' назначена роль '{}'.", username, role)))
+}
 
-    let username = match data.get("username") {
-        Some(serde_json::Value::String(username)) => username,
-        _ => return Err(Message {
-            command: "auth".to_string(),
-            data: Some(serde_json::json!({"status": "err", "message": "Field 'username' is missing or has the wrong type."})),
-        }),
+fn add_to_group(
+    database: &mut UserDatabase,
+    clients: &AuthorizedClients,
+    username: String,
+    group: String,
+) -> io::Result<Option<String>> {
+    let Some(user) = database.users.get_mut(&username) else {
+        return Ok(None);
     };
+    if user.groups.contains(&group) {
+        return Ok(None);
+    }
+    user.groups.push(group.clone());
+    clients.update_groups(&username, &user.groups);
+    database.persist(&username)?;
+    Ok(Some(format!("Пользователь '{}' добавлен в группу '{}'.", username, group)))
+}
 
-    let password = match data.get("password") {
-        Some(serde_json::Value::String(password)) => password,
-        _ => return Err(Message {
-            command: "auth".to_string(),
-            data: Some(serde_json::json!({"status": "err", "message": "The 'password' field is missing or has the wrong type."})),
-        }),
+fn remove_from_group(
+    database: &mut UserDatabase,
+    clients: &AuthorizedClients,
+    username: String,
+    group: String,
+) -> io::Result<Option<String>> {
+    let Some(user) = database.users.get_mut(&username) else {
+        return Ok(None);
     };
+    let Some(position) = user.groups.iter().position(|g| *g == group) else {
+        return Ok(None);
+    };
+    user.groups.remove(position);
+    clients.update_groups(&username, &user.groups);
+    database.persist(&username)?;
+    Ok(Some(format!("Пользователь '{}' исключён из группы '{}'.", username, group)))
+}
 
-    let db = database.lock().unwrap();
-    if let Some(token) = get_token(&db, username.to_string()) {
-        return Ok(Message {
-            command: "auth".to_string(),
-            data: Some(serde_json::json!({"status": "ok", "message": token})),
-        });
+// Снимает блокировку входа с учётной записи или, если передан IP-адрес, с адреса.
+fn unlock(database: &mut UserDatabase, identifier: String) -> Option<String> {
+    let unlocked = match identifier.parse::<std::net::IpAddr>() {
+        Ok(ip) => database.login_guard.unlock_addr(&ip),
+        Err(_) => database.login_guard.unlock_account(&identifier),
+    };
+    unlocked.then(|| format!("Блокировка входа для '{}' снята.", identifier))
+}
+
+fn del(database: &mut UserDatabase, clients: &AuthorizedClients, username: String) -> io::Result<Option<String>> {
+    let removed = database.retain_sessions(&username, |_| false);
+    clients.close_sessions(&username, &removed, "Account deleted.");
+    if database.users.remove(&username).is_none() {
+        return Ok(None);
     }
-    drop(db);
+    database.persist(&username)?;
+    Ok(Some(format!("Пользователь '{}' удален.", username)))
+}
+
+// Сообщение об ошибке записи базы: изменение уже применено в памяти, но
+// после перезапуска сервера пропадёт.
+fn not_saved(e: &io::Error) -> String {
+    format!("Изменения не сохранены на диск ({}) и пропадут после перезапуска сервера.", e)
+}
+
+fn storage_error(e: io::Error) -> ProtocolError {
+    eprintln!("Ошибка при сохранении базы пользователей: {:?}", e);
+    ProtocolError::new(ErrorCode::Internal, "Failed to save changes.")
+}
 
+// Выполняется в `spawn_blocking`. Пароль проверяется без блокировки базы,
+// чтобы вход одного клиента не задерживал команды остальных.
+fn auth_user(
+    database: Arc<Mutex<UserDatabase>>,
+    request: AuthRequest,
+    addr: std::net::SocketAddr,
+) -> Result<(String, String), ProtocolError> {
+    let AuthRequest { username, password, device } = request;
+
+    let now = unix_time();
+    let password_hash = {
+        let mut db = database.lock().unwrap();
+        let password_hash = db.users.get(&username).map(|user| user.password_hash.clone());
+        if let Err(retry_after) = db.login_guard.check(&username, password_hash.is_some(), addr.ip(), now) {
+            return Err(ProtocolError::new(ErrorCode::Locked, "Too many failed login attempts.")
+                .with("retry_after", retry_after));
+        }
+        password_hash
+    };
+    let rehash = verify_credentials(&password, password_hash.as_deref());
+
+    let mut db = database.lock().unwrap();
+    let token = match rehash.zip(password_hash) {
+        Some((rehash, password_hash)) => {
+            start_session(&mut db, &username, &password_hash, rehash, Some(addr.to_string()), device)
+        }
+        None => Ok(None),
+    };
+    match token {
+        Ok(Some(token)) => {
+            db.login_guard.record_success(&username, addr.ip());
+            Ok((username, token))
+        }
+        Ok(None) => {
+            let known_user = db.users.contains_key(&username);
+            db.login_guard.record_failure(&username, known_user, addr.ip(), now);
+            Err(ProtocolError::new(ErrorCode::InvalidCredentials, "Invalid username or password."))
+        }
+        Err(e) => {
+            db.login_guard.record_success(&username, addr.ip());
+            Err(storage_error(e))
+        }
+    }
+}
+
+fn refresh_user(
+    database: Arc<Mutex<UserDatabase>>,
+    clients: &AuthorizedClients,
+    request: TokenRequest,
+) -> Result<Response, ProtocolError> {
     let mut db = database.lock().unwrap();
-    match auth(&mut db, username.to_string(), password.to_string()) {
-        Some(token) => Ok(Message {
-                command: "auth".to_string(),
-                data: Some(serde_json::json!({"status": "ok", "message": token})),
-            }),
-        None => Err(Message {
-            command: "auth".to_string(),
-            data: Some(serde_json::json!({"status": "err", "message": "Invalid username or password."})),
-        }),
+    match refresh(&mut db, clients, &request.token).map_err(storage_error)? {
+        Some(token) => Ok(Response::Refresh(Status::ok(token))),
+        None => Err(ProtocolError::new(ErrorCode::InvalidToken, "Token is invalid or expired.")),
+    }
+}
+
+fn logout_user(
+    database: Arc<Mutex<UserDatabase>>,
+    clients: &AuthorizedClients,
+    request: LogoutRequest,
+) -> Result<Response, ProtocolError> {
+    let mut db = database.lock().unwrap();
+    let Some(username) = db.find_user_by_token(&request.token) else {
+        return Err(ProtocolError::new(ErrorCode::InvalidToken, "Token is invalid or expired."));
+    };
+    let identifier = if request.all { username } else { request.token };
+    match logout(&mut db, clients, identifier).map_err(storage_error)? {
+        Some(_) => Ok(Response::Logout(Status::ok("Logged out."))),
+        None => Err(ProtocolError::new(ErrorCode::InvalidToken, "Token is invalid or expired.")),
     }
 }
 
 fn message_handler(
     database: Arc<Mutex<UserDatabase>>,
-    msg: Message,
-) -> Result<Message, Box<dyn std::error::Error>> {
-    if let Some(data) = &msg.data {
-        if let Some(data_object) = data.as_object() {
-            if let Some(token_value) = data_object.get("token") {
-                if let Some(token) = token_value.as_str() {
-                    let db = database.lock().unwrap();
-                    if let Some(user) = db.find_user_by_token(token) {
-                        let username = user.clone();
-                        return Ok(Message {
-                            command: msg.command,
-                            data: Some(json!({"sender": username, "msg": data.clone()})),
-                        });
-                    } else {
-                        return Err("Token is invalid.".into());
-                    }
-                }
+    clients: &AuthorizedClients,
+    config: &Config,
+    request: MessageRequest,
+) -> Result<Response, ProtocolError> {
+    let mut db = database.lock().unwrap();
+    let Some(sender) = db.touch_token(&request.token) else {
+        return Err(ProtocolError::new(ErrorCode::InvalidToken, "Token is invalid or expired."));
+    };
+    let role = db.users[&sender].role;
+    let to = request.to;
+    if let Some(Recipient::User(name)) = &to {
+        if !db.users.contains_key(name) {
+            return Err(ProtocolError::new(ErrorCode::UnknownRecipient, "Recipient does not exist."));
+        }
+    }
+
+    // Без права рассылки адресовать можно только тех, кто читает отчёты.
+    if !role.allows(Permission::Broadcast) {
+        let target_role = match &to {
+            Some(Recipient::Role(target)) => Some(*target),
+            Some(Recipient::User(name)) => db.users.get(name).map(|user| user.role),
+            _ => None,
+        };
+        if target_role.is_some_and(|target| !target.allows(Permission::ReceiveReports)) {
+            return Err(ProtocolError::new(ErrorCode::PermissionDenied, "Permission denied."));
+        }
+    }
+
+    let broadcast = Broadcast { sender, msg: request.payload, seq: None, queued_at: None };
+    let event = Response::Message(broadcast.clone()).into_message(None);
+    // Агенты не могут отдавать команды другим агентам: их сообщения
+    // получают только клиенты с правом чтения отчётов.
+    let delivered = if role.allows(Permission::Broadcast) {
+        clients.send(&event, to.as_ref(), |_| true)
+    } else if role.allows(Permission::Report) {
+        clients.send(&event, to.as_ref(), |role| role.allows(Permission::ReceiveReports))
+    } else {
+        return Err(ProtocolError::new(ErrorCode::PermissionDenied, "Permission denied."));
+    };
+
+    match to {
+        Some(Recipient::User(name)) if delivered == 0 => {
+            let Some(user) = db.users.get_mut(&name) else {
+                return Err(ProtocolError::new(ErrorCode::UnknownRecipient, "Recipient does not exist."));
+            };
+            let now = unix_time();
+            let queued = user.outbox.push(broadcast.sender, broadcast.msg, now, config.outbox_ttl, config.outbox_max_depth);
+            if queued.is_none() {
+                return Err(ProtocolError::new(ErrorCode::OutboxFull, "Recipient's offline queue is full."));
             }
+            db.persist(&name).map_err(storage_error)?;
+            Ok(Response::Delivered(Delivery { status: "queued", delivered }))
+        }
+        Some(_) if delivered == 0 => {
+            Err(ProtocolError::new(ErrorCode::RecipientOffline, "Recipient is not connected."))
         }
-        return Err("Token missing or data is not an object.".into());
+        _ => Ok(Response::Delivered(Delivery { status: "ok", delivered })),
     }
-    Err("No data in message.".into())
 }
 
-fn database_manage(users: Arc<Mutex<UserDatabase>>) {
+fn ack_user(database: Arc<Mutex<UserDatabase>>, request: AckRequest) -> Result<Response, ProtocolError> {
+    let mut db = database.lock().unwrap();
+    let Some(username) = db.touch_token(&request.token) else {
+        return Err(ProtocolError::new(ErrorCode::InvalidToken, "Token is invalid or expired."));
+    };
+    let removed = db.users.get_mut(&username).map_or(0, |user| user.outbox.ack(request.seq));
+    if removed > 0 {
+        db.persist(&username).map_err(storage_error)?;
+    }
+    Ok(Response::Ack(Acknowledged { status: "ok", removed }))
+}
+
+fn presence_query(
+    database: Arc<Mutex<UserDatabase>>,
+    clients: &AuthorizedClients,
+    request: TokenRequest,
+) -> Result<Response, ProtocolError> {
+    let mut db = database.lock().unwrap();
+    let Some(username) = db.touch_token(&request.token) else {
+        return Err(ProtocolError::new(ErrorCode::InvalidToken, "Token is invalid or expired."));
+    };
+    if !db.users[&username].role.allows(Permission::ViewPresence) {
+        return Err(ProtocolError::new(ErrorCode::PermissionDenied, "Permission denied."));
+    }
+    Ok(Response::Presence(OnlineUsers { status: "ok", users: clients.presence() }))
+}
+
+fn presence_subscription(
+    database: Arc<Mutex<UserDatabase>>,
+    clients: &AuthorizedClients,
+    outbound: &Outbound,
+    request: TokenRequest,
+    enabled: bool,
+) -> Result<Status, ProtocolError> {
+    let mut db = database.lock().unwrap();
+    let Some(username) = db.touch_token(&request.token) else {
+        return Err(ProtocolError::new(ErrorCode::InvalidToken, "Token is invalid or expired."));
+    };
+    if !db.users[&username].role.allows(Permission::ViewPresence) {
+        return Err(ProtocolError::new(ErrorCode::PermissionDenied, "Permission denied."));
+    }
+    drop(db);
+    if !clients.subscribe_presence(outbound, enabled) {
+        return Err(ProtocolError::new(ErrorCode::InvalidRequest, "Authenticate on this connection first."));
+    }
+    Ok(Status::ok(if enabled { "Subscribed to presence events." } else { "Unsubscribed from presence events." }))
+}
+
+fn channel_subscription(
+    database: Arc<Mutex<UserDatabase>>,
+    clients: &AuthorizedClients,
+    config: &Config,
+    outbound: &Outbound,
+    request: ChannelRequest,
+    subscribe: bool,
+) -> Result<Status, ProtocolError> {
+    if !channels::is_valid_name(&request.channel, true) {
+        return Err(ProtocolError::new(ErrorCode::InvalidRequest, "Invalid channel name."));
+    }
+    let mut db = database.lock().unwrap();
+    let Some(username) = db.touch_token(&request.token) else {
+        return Err(ProtocolError::new(ErrorCode::InvalidToken, "Token is invalid or expired."));
+    };
+    let role = db.users[&username].role;
+    drop(db);
+
+    let changed = if subscribe {
+        if !config.channels.can_subscribe(&request.channel, role) {
+            return Err(ProtocolError::new(ErrorCode::PermissionDenied, "Permission denied."));
+        }
+        clients.subscribe_channel(outbound, &request.channel)
+    } else {
+        clients.unsubscribe_channel(outbound, &request.channel)
+    };
+    match changed {
+        Some(true) => Ok(Status::ok(request.channel)),
+        Some(false) if subscribe => Err(ProtocolError::new(ErrorCode::InvalidRequest, "Already subscribed.")),
+        Some(false) => Err(ProtocolError::new(ErrorCode::InvalidRequest, "Not subscribed.")),
+        None => Err(ProtocolError::new(ErrorCode::InvalidRequest, "Authenticate on this connection first.")),
+    }
+}
+
+fn publish_channel(
+    database: Arc<Mutex<UserDatabase>>,
+    clients: &AuthorizedClients,
+    config: &Config,
+    request: PublishRequest,
+) -> Result<Response, ProtocolError> {
+    if !channels::is_valid_name(&request.channel, false) {
+        return Err(ProtocolError::new(ErrorCode::InvalidRequest, "Invalid channel name."));
+    }
+    let mut db = database.lock().unwrap();
+    let Some(sender) = db.touch_token(&request.token) else {
+        return Err(ProtocolError::new(ErrorCode::InvalidToken, "Token is invalid or expired."));
+    };
+    if !config.channels.can_publish(&request.channel, db.users[&sender].role) {
+        return Err(ProtocolError::new(ErrorCode::PermissionDenied, "Permission denied."));
+    }
+    drop(db);
+
+    let channel = request.channel.clone();
+    let event = Response::Publish(Publication { channel: request.channel, sender, msg: request.payload });
+    let delivered = clients.publish(&channel, &event.into_message(None), &config.channels);
+    Ok(Response::Published(Delivery { status: "ok", delivered }))
+}
+
+// Сообщения, накопившиеся для пользователя, пока он был не в сети.
+fn queued_messages(database: &Mutex<UserDatabase>, username: &str) -> Vec<Message> {
+    let now = unix_time();
+    let db = database.lock().unwrap();
+    let Some(user) = db.users.get(username) else {
+        return Vec::new();
+    };
+    user.outbox.pending(now)
+        .map(|item| {
+            Response::Message(Broadcast {
+                sender: item.sender.clone(),
+                msg: item.msg.clone(),
+                seq: Some(item.seq),
+                queued_at: Some(item.queued_at),
+            })
+            .into_message(None)
+        })
+        .collect()
+}
+
+// База блокируется на время одной команды; `add` и `auth` хэшируют пароль без блокировки.
+fn execute_command(database: &Mutex<UserDatabase>, clients: &AuthorizedClients, parts: &[&str]) -> Vec<String> {
+    let db = || database.lock().unwrap();
+    let mut output = Vec::new();
+    match parts {
+        ["presence"] => {
+            let presence = clients.presence();
+            if presence.is_empty() {
+                output.push("Нет пользователей в сети.".to_string());
+            } else {
+                let now = unix_time();
+                output.push("Пользователи в сети:".to_string());
+                output.extend(presence.into_iter().map(|p| format!(
+                    "{} | роль: {} | соединений: {} | активен {} с назад",
+                    p.username,
+                    p.role,
+                    p.connections,
+                    now.saturating_sub(p.last_seen),
+                )));
+            }
+        },
+        ["list"] => {
+            if let Some(users_list) = list(&db()) {
+                output.push("Список пользователей:".to_string());
+                output.extend(users_list);
+            } else {
+                output.push("Нет пользователей.".to_string());
+            }
+        },
+        ["add", username, password] => match add(database, username.to_string(), password.to_string()) {
+            Ok(Some(message)) => output.push(message),
+            Ok(None) => output.push("Ошибка: Пользователь с таким именем уже существует.".to_string()),
+            Err(e) => output.push(not_saved(&e)),
+        },
+        ["auth", username, password] => match auth(database, username.to_string(), password.to_string(), None, None) {
+            Ok(Some(message)) => output.push(message),
+            Ok(None) => output.push("Ошибка аутентификации.".to_string()),
+            Err(e) => output.push(not_saved(&e)),
+        },
+        ["logout", identifier] => match logout(&mut db(), clients, identifier.to_string()) {
+            Ok(Some(message)) => output.push(message),
+            Ok(None) => output.push("Ошибка: Пользователь или токен не найден.".to_string()),
+            Err(e) => output.push(not_saved(&e)),
+        },
+        ["logout", username, session_id] => match logout_session(&mut db(), clients, username.to_string(), session_id.to_string()) {
+            Ok(Some(message)) => output.push(message),
+            Ok(None) => output.push(format!("Ошибка: Сессия '{}' пользователя '{}' не найдена.", session_id, username)),
+            Err(e) => output.push(not_saved(&e)),
+        },
+        ["del", username] => match del(&mut db(), clients, username.to_string()) {
+            Ok(Some(message)) => output.push(message),
+            Ok(None) => output.push(format!("Ошибка: Пользователь '{}' не найден.", username)),
+            Err(e) => output.push(not_saved(&e)),
+        },
+        ["unlock", identifier] => match unlock(&mut db(), identifier.to_string()) {
+            Some(message) => output.push(message),
+            None => output.push(format!("Ошибка: '{}' не заблокирован.", identifier)),
+        },
+        ["role", username] => match db().users.get(*username) {
+            Some(user) => output.push(format!("Роль пользователя '{}': {}.", username, user.role)),
+            None => output.push(format!("Ошибка: Пользователь '{}' не найден.", username)),
+        },
+        ["role", username, role] => match role.parse::<Role>() {
+            Ok(role) => match set_role(&mut db(), clients, username.to_string(), role) {
+                Ok(Some(message)) => output.push(message),
+                Ok(None) => output.push(format!("Ошибка: Пользователь '{}' не найден.", username)),
+                Err(e) => output.push(not_saved(&e)),
+            },
+            Err(e) => output.push(format!("Ошибка: {}.", e)),
+        },
+        ["groups", username] => match db().users.get(*username) {
+            Some(user) if user.groups.is_empty() => output.push(format!("Пользователь '{}' не состоит в группах.", username)),
+            Some(user) => output.push(format!("Группы пользователя '{}': {}.", username, user.groups.join(", "))),
+            None => output.push(format!("Ошибка: Пользователь '{}' не найден.", username)),
+        },
+        ["group", username, group] => match add_to_group(&mut db(), clients, username.to_string(), group.to_string()) {
+            Ok(Some(message)) => output.push(message),
+            Ok(None) => output.push(format!("Ошибка: Пользователь '{}' не найден или уже состоит в группе.", username)),
+            Err(e) => output.push(not_saved(&e)),
+        },
+        ["ungroup", username, group] => match remove_from_group(&mut db(), clients, username.to_string(), group.to_string()) {
+            Ok(Some(message)) => output.push(message),
+            Ok(None) => output.push(format!("Ошибка: Пользователь '{}' не найден или не состоит в группе.", username)),
+            Err(e) => output.push(not_saved(&e)),
+        },
+        ["sessions", username] => match sessions(&db(), username.to_string()) {
+            Some(sessions) if sessions.is_empty() => output.push(format!("У пользователя '{}' нет активных сессий.", username)),
+            Some(sessions) => {
+                let now = unix_time();
+                output.push(format!("Сессии пользователя '{}':", username));
+                output.extend(sessions.into_iter().map(|s| describe_session(s, now)));
+            }
+            None => output.push(format!("Ошибка: Пользователь '{}' не найден.", username)),
+        },
+        _ => output.push("Неизвестная команда, попробуйте ещё раз.".to_string()),
+    }
+    output
+}
+
+fn database_manage(users: Arc<Mutex<UserDatabase>>, clients: AuthorizedClients, shutdown: CancellationToken) {
     println!("Запущена программа управления базы пользователей.\n");
 
     loop {
@@ -211,168 +883,377 @@ fn database_manage(users: Arc<Mutex<UserDatabase>>) {
         println!("1. list - Выводит список пользователей.");
         println!("2. add <username> <password> - Добавляет пользователя.");
         println!("3. auth <username> <password> - Возвращает/генерирует токен (ключ сессии).");
-        println!("4. logout <username/token> - Удаляет токен у соответствующего пользователя.");
+        println!("4. logout <username/token> [session] - Завершает сессию по токену или номеру, либо все сессии пользователя.");
         println!("5. del <username> - Удаляет пользователя.");
-        println!("6. gettoken <username> - Получает токен пользователя.");
+        println!("6. sessions <username> - Выводит активные сессии пользователя.");
+        println!("7. role <username> [admin/operator/agent] - Выводит или назначает роль пользователя.");
+        println!("8. unlock <username/ip> - Снимает блокировку входа после неудачных попыток.");
+        println!("9. groups <username> / group <username> <group> / ungroup <username> <group> - Управляет группами пользователя.");
+        println!("10. presence - Выводит пользователей в сети.");
         println!("0. exit - для выхода.");
         print!(">>> ");
 
         let mut input = String::new();
         io::stdout().flush().unwrap();
-        io::stdin().read_line(&mut input).expect("Не удалось прочитать строку");
+        match io::stdin().read_line(&mut input) {
+            Ok(0) => {
+                println!("\nВвод закрыт, консоль управления отключена. Сервер продолжает работу.");
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Не удалось прочитать строку: {:?}. Консоль управления отключена.", e);
+                return;
+            }
+        }
         let input = input.trim();
         let parts: Vec<&str> = input.split_whitespace().collect();
         print!("\x1B[2J\x1B[1;1H");
         io::stdout().flush().unwrap();
 
-        let mut db = users.lock().unwrap();
-        match parts.as_slice() {
-            ["list"] => {
-                if let Some(users_list) = list(&db) {
-                    println!("Список пользователей:");
-                    for user in users_list {
-                        println!("{}", user);
-                    }
-                } else {
-                    println!("Нет пользователей.");
-                }
-            },
-            ["add", username, password] => match add(&mut db, username.to_string(), password.to_string()) {
-                Some(message) => println!("{}", message),
-                None => println!("Ошибка: Пользователь с таким именем уже существует."),
-            },
-            ["auth", username, password] => match auth(&mut db, username.to_string(), password.to_string()) {
-                Some(message) => println!("{}", message),
-                None => println!("Ошибка аутентификации."),
-            },
-            ["logout", identifier] => match logout(&mut db, identifier.to_string()) {
-                Some(message) => println!("{}", message),
-                None => println!("Ошибка: Пользователь или токен не найден."),
-            },
-            ["del", username] => match del(&mut db, username.to_string()) {
-                Some(message) => println!("{}", message),
-                None => println!("Ошибка: Пользователь '{}' не найден.", username),
-            },
-            ["gettoken", username] => match get_token(&db, username.to_string()) {
-                Some(message) => println!("{}", message),
-                None => println!("Ошибка: Токен для '{}' не найден.", username),
-            },
-            ["exit"] => break,
-            _ => println!("Неизвестная команда, попробуйте ещё раз."),
+        if parts.as_slice() == ["exit"] {
+            shutdown.cancel();
+            return;
+        }
+
+        for line in execute_command(&users, &clients, &parts) {
+            println!("{}", line);
         }
     }
 }
 
-async fn handle_client(
-    mut socket: TcpStream,
+async fn handle_client<S>(
+    socket: S,
     addr: std::net::SocketAddr,
     database: Arc<Mutex<UserDatabase>>,
     clients: AuthorizedClients,
-) {
-    let mut buf = vec![0; 1024];
+    config: Arc<Config>,
+    limiter: RateLimiter,
+    shutdown: CancellationToken,
+)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
     println!("Клиент подключился: {}", addr);
 
-    let username: Option<String> = None;
-    
+    let (reader, writer) = tokio::io::split(socket);
+    let mut reader = FramedRead::new(reader, MessageCodec::new(config.max_frame_length));
+    let mut writer = FramedWrite::new(writer, MessageCodec::new(config.max_frame_length));
+    let (outbound, mut outbound_rx) = mpsc::channel::<Message>(config.outbound_queue_capacity);
+
+    let writer_task = tokio::spawn(async move {
+        while let Some(msg) = outbound_rx.recv().await {
+            if let Err(e) = writer.send(msg).await {
+                eprintln!("Ошибка при отправке данных клиенту {}: {:?}", addr, e);
+                break;
+            }
+        }
+        let _ = writer.close().await;
+    });
+
+    let mut username: Option<String> = None;
+    let mut rate_limits = ConnectionLimits::default();
+    let mut backlog: Vec<Message> = Vec::new();
+    let last_seen = Arc::new(AtomicU64::new(unix_time()));
+    let closed = CancellationToken::new();
+    let mut idle_deadline = Instant::now() + config.heartbeat_timeout();
+
     loop {
-        let n = match socket.read(&mut buf).await {
-            Ok(n) if n == 0 => {
+        let next = tokio::select! {
+            _ = shutdown.cancelled() => {
+                let notice = Response::ServerShutdown(Notice { message: "Server is shutting down.".to_string() });
+                let _ = outbound.send(notice.into_message(None)).await;
+                break;
+            }
+            _ = closed.cancelled() => {
+                println!("Сессия клиента {} завершена, соединение закрыто.", addr);
+                break;
+            }
+            _ = sleep_until(idle_deadline) => {
+                // Полуоткрытое TCP-соединение не даёт ни данных, ни ошибки,
+                // поэтому молчащий дольше heartbeat_timeout клиент считается отключившимся.
+                println!("Клиент {} не отвечает {} с, соединение закрыто.", addr, config.heartbeat_timeout);
+                break;
+            }
+            next = reader.next() => next,
+        };
+        idle_deadline = Instant::now() + config.heartbeat_timeout();
+        last_seen.store(unix_time(), Ordering::Relaxed);
+        let msg = match next {
+            None => {
                 println!("Клиент {} отключился.", addr);
-                if let Some(username) = &username {
-                    clients.lock().unwrap().remove(username);
-                }
-                return;
+                break;
             }
-            Ok(n) => n,
-            Err(e) => {
+            Some(Ok(msg)) => msg,
+            Some(Err(CodecError::FrameTooLong)) => {
+                // После слишком длинного кадра поток не восстановить, поэтому
+                // клиент получает объяснение и соединение закрывается.
+                let error = ProtocolError::new(
+                    ErrorCode::FrameTooLong,
+                    format!("Frame exceeds {} bytes.", config.max_frame_length),
+                );
+                let _ = outbound.send(error.into_message("error", None)).await;
+                break;
+            }
+            Some(Err(e)) => {
                 eprintln!("Ошибка при чтении от клиента {}: {:?}", addr, e);
-                if let Some(username) = &username {
-                    clients.lock().unwrap().remove(username);
-                }
-                return;
+                break;
             }
         };
 
-        let Ok(msg) = serde_json::from_slice::<Message>(&buf[..n]) else {
-            eprintln!("Ошибка преобразования JSON от клиента.");
-            continue;
-        };
-        
-        match msg.command.as_str() {
-            "auth" => { 
-                let response = match auth_user(database.clone(), msg.clone()) {
-                    Ok(msg) => {
-                        if let Some(name) = username.clone() { 
-                            clients.lock().unwrap().insert(name, socket);
-                        } else {
-                            eprintln!("Ошибка: имя пользователя отсутствует.");
-                        }
-                        
-                        msg},
-                    Err(msg) => msg,
-                };
-                let Ok(response_json) = serde_json::to_vec(&response) else {
-                    eprintln!("Ошибка преобразования JSON для клиента.");
-                    continue;
-                };
-                if let Err(e) = socket.write_all(&response_json).await {
-                    eprintln!("Ошибка при отправке данных клиенту {}: {:?}", addr, e);
-                    return;
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(e) => {
+                let error = ProtocolError::new(ErrorCode::InvalidJson, format!("Invalid JSON: {}.", e));
+                if outbound.send(error.into_message("error", None)).await.is_err() {
+                    break;
                 }
+                continue;
             }
-            "message" => {
-                match message_handler(database.clone(), msg) {
-                    Ok(response) => {
-                        let serialized = serde_json::to_vec(&response).unwrap();
-                        let mut clients_guard = clients.lock().unwrap();
-                        for (_, client_socket) in clients_guard.iter_mut() {
-                            if let Err(e) = client_socket.write_all(&serialized).await {
-                                eprintln!("Ошибка при отправке сообщения клиенту: {:?}", e);
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("Ошибка обработки сообщения: {:?}", e);
-                        if let Some(username) = &username {
-                            clients.lock().unwrap().remove(username);
-                        }
-                        return;
+        };
+
+        // Роль берётся из реестра при каждой команде, чтобы смена роли сразу меняла лимиты.
+        let user = username.as_deref().and_then(|name| Some((name, clients.role(name, &outbound)?)));
+        let result = if let Err(retry_after) = limiter.check(&mut rate_limits, &msg.command, user) {
+            Err(ProtocolError::new(ErrorCode::RateLimited, "Rate limit exceeded.").with("retry_after", retry_after))
+        } else if msg.kind != MessageType::Request {
+            Err(ProtocolError::new(ErrorCode::InvalidRequest, "Clients may only send messages of type 'request'."))
+        } else {
+            match Request::parse(&msg) {
+                Err(error) => Err(error),
+                Ok(Request::Auth(request)) => {
+                    let database_auth = database.clone();
+                    let result = tokio::task::spawn_blocking(move || auth_user(database_auth, request, addr))
+                        .await
+                        .unwrap_or_else(|_| Err(ProtocolError::new(ErrorCode::Internal, "Internal server error.")));
+                    result.map(|(name, token)| {
+                        // Повторная аутентификация на том же соединении сохраняет его подписки.
+                        let subscriptions = match username.replace(name.clone()) {
+                            Some(previous) => clients.unregister(&previous, &outbound),
+                            None => Subscriptions::default(),
+                        };
+                        let (session, user_role, groups) = {
+                            let mut db = database.lock().unwrap();
+                            let session = db.find_session_mut(&token).map(|(_, s)| s.id.clone()).unwrap_or_default();
+                            let user = &db.users[&name];
+                            (session, user.role, user.groups.clone())
+                        };
+                        let handle = ConnectionHandle {
+                            outbound: outbound.clone(),
+                            last_seen: last_seen.clone(),
+                            closed: closed.clone(),
+                        };
+                        clients.register(name.clone(), session, user_role, groups, handle, subscriptions);
+                        // Очередь читается после регистрации: сообщение, отправленное между
+                        // ними, иначе не попало бы ни в соединение, ни в уже прочитанную очередь.
+                        // Дубликат при этом возможен, но подтверждение идёт по номеру.
+                        backlog = queued_messages(&database, &name);
+                        Response::Auth(Status::ok(token))
+                    })
+                }
+                // Команды, изменяющие базу, ждут записи журнала на диск; block_in_place
+                // передаёт остальные задачи этого рабочего потока другим потокам.
+                Ok(Request::Logout(request)) => block_in_place(|| logout_user(database.clone(), &clients, request)),
+                Ok(Request::Refresh(request)) => block_in_place(|| refresh_user(database.clone(), &clients, request)),
+                Ok(Request::Message(request)) => {
+                    block_in_place(|| message_handler(database.clone(), &clients, &config, request))
+                }
+                Ok(Request::Ack(request)) => block_in_place(|| ack_user(database.clone(), request)),
+                Ok(Request::Ping(request)) => {
+                    if let Some(token) = request.and_then(|request| request.token) {
+                        database.lock().unwrap().touch_token(&token);
                     }
+                    Ok(Response::Pong(Heartbeat {
+                        interval: config.heartbeat_interval,
+                        timeout: config.heartbeat_timeout,
+                    }))
+                }
+                Ok(Request::Presence(request)) => presence_query(database.clone(), &clients, request),
+                Ok(Request::PresenceSubscribe(request)) => {
+                    presence_subscription(database.clone(), &clients, &outbound, request, true)
+                        .map(Response::PresenceSubscribe)
                 }
+                Ok(Request::PresenceUnsubscribe(request)) => {
+                    presence_subscription(database.clone(), &clients, &outbound, request, false)
+                        .map(Response::PresenceUnsubscribe)
+                }
+                Ok(Request::Subscribe(request)) => {
+                    channel_subscription(database.clone(), &clients, &config, &outbound, request, true)
+                        .map(Response::Subscribe)
+                }
+                Ok(Request::Unsubscribe(request)) => {
+                    channel_subscription(database.clone(), &clients, &config, &outbound, request, false)
+                        .map(Response::Unsubscribe)
+                }
+                Ok(Request::Publish(request)) => publish_channel(database.clone(), &clients, &config, request),
             }
-            _ => {
-                eprintln!("Неизвестная команда от клиента {}: {:?}", addr, msg.command);
+        };
+
+        let response = match result {
+            Ok(response) => response.into_message(msg.id),
+            Err(error) => error.into_message(&msg.command, msg.id),
+        };
+        if outbound.send(response).await.is_err() {
+            break;
+        }
+        for queued in backlog.drain(..) {
+            if outbound.send(queued).await.is_err() {
+                break;
             }
         }
     }
+
+    if let Some(username) = &username {
+        clients.unregister(username, &outbound);
+    }
+    drop(outbound);
+    let _ = writer_task.await;
+}
+
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(e) => {
+                eprintln!("Не удалось подписаться на SIGTERM: {:?}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let users = Arc::new(Mutex::new(UserDatabase::new()));
-    let clients = Arc::new(Mutex::new(HashMap::new()));
-    let listener = TcpListener::bind("127.0.0.1:8080").await?;
-    println!("Сервер запущен на 127.0.0.1");
-    let users_manage = users.clone();
-    let dat_man = thread::spawn(move || database_manage(users_manage));
+    let config = match Config::load() {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            eprintln!("Ошибка конфигурации: {}", e);
+            std::process::exit(2);
+        }
+    };
 
-    loop {
-        if dat_man.is_finished() {
-            return Ok(());
+    let store: Box<dyn UserStore> = match config.storage {
+        StorageBackend::Memory => Box::new(MemoryStore),
+        StorageBackend::File => Box::new(FileStore::new(&config.database_path)),
+    };
+    let token_hasher = TokenHasher::load_or_create(&config.secret_key_path)?;
+    let token_policy = TokenPolicy {
+        length: config.token_length,
+        lifetime: config.token_lifetime,
+        idle_timeout: config.token_idle_timeout,
+    };
+    let login_guard = LoginGuard::new(LockoutPolicy {
+        max_failures: config.auth_max_failures,
+        backoff_base: config.auth_backoff_base,
+        backoff_max: config.auth_backoff_max,
+        lockout: config.auth_lockout,
+    });
+    let users = Arc::new(Mutex::new(UserDatabase::open(store, token_hasher, token_policy, login_guard)?));
+    let clients = AuthorizedClients::new();
+    let limiter = RateLimiter::new(config.rate_limits.clone());
+    let tls_acceptor = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert_path), Some(key_path)) => Some(tls::acceptor(TlsSettings {
+            cert_path: cert_path.clone(),
+            key_path: key_path.clone(),
+            client_ca_path: config.tls_client_ca.clone(),
+        })?),
+        _ => None,
+    };
+    let listener = TcpListener::bind(config.bind).await?;
+    println!("Сервер запущен на {}", config.bind);
+    let shutdown = CancellationToken::new();
+    let connections = TaskTracker::new();
+
+    let shutdown_signal = shutdown.clone();
+    tokio::spawn(async move {
+        wait_for_signal().await;
+        println!("Получен сигнал остановки.");
+        shutdown_signal.cancel();
+    });
+
+    #[cfg(unix)]
+    if let Some(path) = &config.control_socket {
+        let control_listener = control::bind(path, config.control_socket_mode)?;
+        println!("Канал управления: {}", path.display());
+        tokio::spawn(control::serve(control_listener, path.clone(), users.clone(), clients.clone(), shutdown.clone()));
+    }
+
+    if let (Some(addr), Some(api_key)) = (config.http_bind, &config.admin_api_key) {
+        let http_listener = TcpListener::bind(addr).await?;
+        println!("HTTP API управления запущен на {}", addr);
+        tokio::spawn(admin_api::serve(http_listener, users.clone(), clients.clone(), api_key.clone(), shutdown.clone()));
+    }
+
+    if config.daemon {
+        if config.control_socket.is_none() && config.http_bind.is_none() {
+            eprintln!("Внимание: сервер запущен без консоли и без канала управления.");
         }
+    } else {
+        let users_manage = users.clone();
+        let clients_manage = clients.clone();
+        let shutdown_manage = shutdown.clone();
+        thread::spawn(move || database_manage(users_manage, clients_manage, shutdown_manage));
+    }
 
-        match timeout(Duration::from_secs(2), listener.accept()).await {
-            Ok(Ok((socket, addr))) => {
+    loop {
+        let accepted = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            accepted = listener.accept() => accepted,
+        };
+        match accepted {
+            Ok((socket, addr)) => {
                 let users_clone = users.clone();
                 let clients_clone = clients.clone();
-                tokio::spawn(handle_client(socket, addr, users_clone, clients_clone));
+                let config_clone = config.clone();
+                let limiter_clone = limiter.clone();
+                let shutdown_clone = shutdown.clone();
+                match tls_acceptor.clone() {
+                    Some(acceptor) => {
+                        connections.spawn(async move {
+                            match timeout(config_clone.tls_handshake_timeout(), acceptor.accept(socket)).await {
+                                Ok(Ok(stream)) => {
+                                    handle_client(
+                                        stream, addr, users_clone, clients_clone, config_clone, limiter_clone, shutdown_clone,
+                                    ).await
+                                }
+                                Ok(Err(e)) => eprintln!("Ошибка TLS-рукопожатия с клиентом {}: {:?}", addr, e),
+                                Err(_) => eprintln!("Истекло время TLS-рукопожатия с клиентом {}.", addr),
+                            }
+                        });
+                    }
+                    None => {
+                        connections.spawn(handle_client(
+                            socket, addr, users_clone, clients_clone, config_clone, limiter_clone, shutdown_clone,
+                        ));
+                    }
+                }
             }
-            Ok(Err(e)) => {
+            Err(e) => {
                 eprintln!("Ошибка при подключении: {:?}", e);
             }
-            Err(_) => {
-                print!("");
-            }  
         }
-    } 
+    }
+
+    // Новые соединения больше не принимаются; ждём, пока клиенты получат
+    // "server_shutdown" и их задачи допишут исходящие очереди.
+    drop(listener);
+    println!("Сервер останавливается, ожидание завершения соединений...");
+    connections.close();
+    if timeout(config.shutdown_timeout(), connections.wait()).await.is_err() {
+        eprintln!("Не все соединения завершились за {} с, они будут прерваны.", config.shutdown_timeout);
+    }
+    if let Err(e) = users.lock().unwrap().snapshot() {
+        eprintln!("Ошибка при сохранении базы пользователей: {:?}", e);
+    }
+    println!("Сервер остановлен.");
+    Ok(())
 }
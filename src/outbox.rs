@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::VecDeque;
+
+/// Сообщение, ожидающее подключения адресата.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct QueuedMessage {
+    pub seq: u64,
+    pub sender: String,
+    pub msg: Map<String, Value>,
+    pub queued_at: u64,
+    pub expires_at: u64,
+}
+
+/// Очередь сообщений пользователя, который был не в сети. Сообщения
+/// отправляются при каждой аутентификации и удаляются только командой `ack`,
+/// поэтому обрыв связи во время доставки их не теряет.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Outbox {
+    next_seq: u64,
+    items: VecDeque<QueuedMessage>,
+}
+
+impl Outbox {
+    /// Очередь ни разу не использовалась. Пустую очередь с ненулевым `next_seq`
+    /// сохранять нужно: иначе после перезапуска номера начнутся заново с 1, и клиент,
+    /// отбрасывающий уже подтверждённые номера, потеряет новые сообщения.
+    pub fn is_unused(&self) -> bool {
+        self.next_seq == 0 && self.items.is_empty()
+    }
+
+    pub fn prune_expired(&mut self, now: u64) {
+        self.items.retain(|item| now < item.expires_at);
+    }
+
+    /// Ставит сообщение в очередь и возвращает его номер или `None`, если очередь заполнена.
+    pub fn push(&mut self, sender: String, msg: Map<String, Value>, now: u64, ttl: u64, max_depth: usize) -> Option<u64> {
+        self.prune_expired(now);
+        if self.items.len() >= max_depth {
+            return None;
+        }
+        self.next_seq += 1;
+        self.items.push_back(QueuedMessage {
+            seq: self.next_seq,
+            sender,
+            msg,
+            queued_at: now,
+            expires_at: now + ttl,
+        });
+        Some(self.next_seq)
+    }
+
+    pub fn pending(&self, now: u64) -> impl Iterator<Item = &QueuedMessage> {
+        self.items.iter().filter(move |item| now < item.expires_at)
+    }
+
+    /// Удаляет сообщения с номерами до `seq` включительно и возвращает их число.
+    pub fn ack(&mut self, seq: u64) -> usize {
+        let before = self.items.len();
+        self.items.retain(|item| item.seq > seq);
+        before - self.items.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push(outbox: &mut Outbox, now: u64) -> Option<u64> {
+        outbox.push("alice".to_string(), Map::new(), now, 10, 3)
+    }
+
+    fn pending(outbox: &Outbox, now: u64) -> Vec<u64> {
+        outbox.pending(now).map(|item| item.seq).collect()
+    }
+
+    #[test]
+    fn numbers_messages_in_order() {
+        let mut outbox = Outbox::default();
+        assert!(outbox.is_unused());
+        assert_eq!(push(&mut outbox, 100), Some(1));
+        assert_eq!(push(&mut outbox, 101), Some(2));
+        assert_eq!(pending(&outbox, 101), [1, 2]);
+        assert!(!outbox.is_unused());
+    }
+
+    #[test]
+    fn ack_removes_messages_up_to_seq() {
+        let mut outbox = Outbox::default();
+        for now in 100..103 {
+            push(&mut outbox, now);
+        }
+        assert_eq!(outbox.ack(2), 2);
+        assert_eq!(outbox.ack(2), 0);
+        assert_eq!(pending(&outbox, 103), [3]);
+    }
+
+    #[test]
+    fn expired_messages_are_hidden_and_free_space() {
+        let mut outbox = Outbox::default();
+        push(&mut outbox, 100);
+        push(&mut outbox, 105);
+        push(&mut outbox, 106);
+        assert_eq!(push(&mut outbox, 109), None);
+        assert_eq!(pending(&outbox, 110), [2, 3]);
+        assert_eq!(push(&mut outbox, 110), Some(4));
+    }
+
+    #[test]
+    fn sequence_survives_empty_queue_round_trip() {
+        let mut outbox = Outbox::default();
+        push(&mut outbox, 100);
+        outbox.ack(1);
+        assert!(!outbox.is_unused());
+
+        let mut restored: Outbox = serde_json::from_value(serde_json::to_value(&outbox).unwrap()).unwrap();
+        assert_eq!(push(&mut restored, 101), Some(2));
+    }
+}
@@ -0,0 +1,101 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+
+// Хэш случайного пароля с параметрами по умолчанию. С ним сверяется пароль
+// несуществующего пользователя, чтобы время ответа не выдавало, есть ли такая
+// учётная запись.
+const DUMMY_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$hTaIjPU7WyyEPto9BWJjcA$vFlW2FzpwEXN3+i/uPeYGhuKVEQiONKhk9mcuQbQwSk";
+
+fn hasher() -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, Params::default())
+}
+
+/// Хэширует пароль Argon2id со случайной солью и возвращает строку в формате PHC.
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    hasher()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("Не удалось вычислить хэш пароля")
+        .to_string()
+}
+
+/// Проверяет пароль против сохранённого хэша за постоянное время.
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    let Ok(parsed) = PasswordHash::new(password_hash) else {
+        return false;
+    };
+    hasher().verify_password(password.as_bytes(), &parsed).is_ok()
+}
+
+/// Проверяет пароль пользователя; если пользователя нет (`password_hash` равен
+/// `None`), тратит то же время на проверку фиктивного хэша и возвращает `false`.
+pub fn verify_password_or_dummy(password: &str, password_hash: Option<&str>) -> bool {
+    match password_hash {
+        Some(password_hash) => verify_password(password, password_hash),
+        None => {
+            verify_password(password, DUMMY_HASH);
+            false
+        }
+    }
+}
+
+/// Возвращает `true`, если хэш вычислен с другим алгоритмом или параметрами
+/// и его стоит пересчитать при следующем успешном входе.
+pub fn needs_rehash(password_hash: &str) -> bool {
+    let Ok(parsed) = PasswordHash::new(password_hash) else {
+        return true;
+    };
+    if parsed.algorithm != Algorithm::Argon2id.ident()
+        || parsed.version != Some(Version::V0x13.into())
+    {
+        return true;
+    }
+    let current = Params::default();
+    match Params::try_from(&parsed) {
+        Ok(params) => {
+            params.m_cost() != current.m_cost()
+                || params.t_cost() != current.t_cost()
+                || params.p_cost() != current.p_cost()
+        }
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash_with(algorithm: Algorithm, params: Params) -> String {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::new(algorithm, Version::V0x13, params)
+            .hash_password(b"secret", &salt)
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn verifies_own_hash() {
+        let hash = hash_password("secret");
+        assert!(verify_password("secret", &hash));
+        assert!(!verify_password("Secret", &hash));
+        assert!(!verify_password("secret", "not a hash"));
+        assert!(!needs_rehash(&hash));
+    }
+
+    #[test]
+    fn unknown_user_never_verifies() {
+        assert!(!verify_password_or_dummy("", None));
+        // Фиктивный хэш должен стоить столько же, сколько настоящий.
+        assert!(!needs_rehash(DUMMY_HASH));
+    }
+
+    #[test]
+    fn rehashes_outdated_hashes() {
+        assert!(needs_rehash(&hash_with(Algorithm::Argon2id, Params::new(8 * 1024, 1, 1, None).unwrap())));
+        assert!(needs_rehash(&hash_with(Algorithm::Argon2i, Params::default())));
+        assert!(needs_rehash("$2b$12$R9h/cIPz0gi.URNNX3kh2OPST9/PgBkqquzi.Ss7KIUgO2t0jWMUW"));
+        assert!(needs_rehash("plaintext"));
+    }
+}
@@ -0,0 +1,231 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    error::{ErrorCode, ProtocolError},
+    registry::Presence,
+    roles::Role,
+    Message, MessageType,
+};
+
+/// Команда клиента. Имя варианта совпадает с полем `command`, полезная
+/// нагрузка берётся из поля `data` конверта `Message`.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "command", content = "data", rename_all = "snake_case")]
+pub enum Request {
+    Auth(AuthRequest),
+    Refresh(TokenRequest),
+    Logout(LogoutRequest),
+    Message(MessageRequest),
+    Ack(AckRequest),
+    /// Проверка связи; поле `data` не обязательно.
+    Ping(Option<PingRequest>),
+    Presence(TokenRequest),
+    PresenceSubscribe(TokenRequest),
+    PresenceUnsubscribe(TokenRequest),
+    Subscribe(ChannelRequest),
+    Unsubscribe(ChannelRequest),
+    Publish(PublishRequest),
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AuthRequest {
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub device: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TokenRequest {
+    pub token: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LogoutRequest {
+    pub token: String,
+    /// Завершить все сессии пользователя, а не только сессию этого токена.
+    #[serde(default)]
+    pub all: bool,
+}
+
+/// Адресат сообщения: `{"user": "pc-17"}`, `{"group": "servers"}` или `{"role": "agent"}`.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Recipient {
+    User(String),
+    Group(String),
+    Role(Role),
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MessageRequest {
+    pub token: String,
+    /// Адресат; без него сообщение получают все, кому его разрешено видеть.
+    #[serde(default)]
+    pub to: Option<Recipient>,
+    /// Остальные поля `data` пересылаются получателям как есть.
+    #[serde(flatten)]
+    pub payload: Map<String, Value>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChannelRequest {
+    pub token: String,
+    /// Имя канала или шаблон вида `alerts.*`.
+    pub channel: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PublishRequest {
+    pub token: String,
+    pub channel: String,
+    /// Остальные поля `data` пересылаются подписчикам как есть.
+    #[serde(flatten)]
+    pub payload: Map<String, Value>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PingRequest {
+    /// Если токен указан, его сессия считается активной, как при любом другом запросе.
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AckRequest {
+    pub token: String,
+    /// Номер последнего полученного сообщения из очереди; удаляются все до него включительно.
+    pub seq: u64,
+}
+
+impl Request {
+    /// Разбирает команду конверта. Неизвестная команда, отсутствующее поле и
+    /// поле неверного типа дают одну и ту же ошибку `invalid_request`.
+    pub fn parse(msg: &Message) -> Result<Request, ProtocolError> {
+        serde_json::from_value(json!({"command": msg.command, "data": msg.data}))
+            .map_err(|e| ProtocolError::new(ErrorCode::InvalidRequest, format!("Invalid request: {}.", e)))
+    }
+}
+
+/// Ответ сервера на команду или событие, отправляемое по инициативе сервера.
+#[derive(Serialize, Debug)]
+#[serde(tag = "command", content = "data", rename_all = "snake_case")]
+pub enum Response {
+    Auth(Status),
+    Refresh(Status),
+    Logout(Status),
+    Message(Broadcast),
+    /// Подтверждение отправки `"message"`: число соединений, получивших сообщение.
+    #[serde(rename = "message")]
+    Delivered(Delivery),
+    Ack(Acknowledged),
+    Pong(Heartbeat),
+    Presence(OnlineUsers),
+    PresenceSubscribe(Status),
+    PresenceUnsubscribe(Status),
+    UserOnline(PresenceChange),
+    UserOffline(PresenceChange),
+    Subscribe(Status),
+    Unsubscribe(Status),
+    Publish(Publication),
+    /// Подтверждение публикации: число подписчиков, получивших сообщение.
+    #[serde(rename = "publish")]
+    Published(Delivery),
+    /// Сессия соединения завершена выходом или удалением пользователя; следом соединение закрывается.
+    SessionClosed(Notice),
+    ServerShutdown(Notice),
+}
+
+#[derive(Serialize, Debug)]
+pub struct Status {
+    pub status: &'static str,
+    pub message: String,
+}
+
+impl Status {
+    pub fn ok(message: impl Into<String>) -> Self {
+        Status { status: "ok", message: message.into() }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Broadcast {
+    pub sender: String,
+    pub msg: Map<String, Value>,
+    /// Номер сообщения в очереди получателя; только у доставленных из очереди.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queued_at: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Publication {
+    pub channel: String,
+    pub sender: String,
+    pub msg: Map<String, Value>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Delivery {
+    pub status: &'static str,
+    pub delivered: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Acknowledged {
+    pub status: &'static str,
+    pub removed: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Heartbeat {
+    /// Рекомендуемый интервал между `"ping"`, в секундах.
+    pub interval: u64,
+    /// Через сколько секунд без единого кадра сервер закроет соединение.
+    pub timeout: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct OnlineUsers {
+    pub status: &'static str,
+    pub users: Vec<Presence>,
+}
+
+/// Событие входа или выхода пользователя; `connections` — число его
+/// соединений после изменения.
+#[derive(Serialize, Debug)]
+pub struct PresenceChange {
+    pub username: String,
+    pub role: Role,
+    pub connections: usize,
+    pub at: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Notice {
+    pub message: String,
+}
+
+impl Response {
+    fn kind(&self) -> MessageType {
+        match self {
+            Response::Auth(_) | Response::Refresh(_) | Response::Logout(_) | Response::Delivered(_) | Response::Ack(_)
+            | Response::Pong(_) | Response::Presence(_) | Response::PresenceSubscribe(_)
+            | Response::PresenceUnsubscribe(_) | Response::Subscribe(_) | Response::Unsubscribe(_)
+            | Response::Published(_) => MessageType::Response,
+            Response::Message(_) | Response::UserOnline(_) | Response::UserOffline(_) | Response::Publish(_)
+            | Response::SessionClosed(_) | Response::ServerShutdown(_) => MessageType::Event,
+        }
+    }
+
+    /// Упаковывает ответ в конверт `Message`; ответ на запрос получает его `id`.
+    pub fn into_message(self, id: Option<Value>) -> Message {
+        let kind = self.kind();
+        let mut value = serde_json::to_value(self).unwrap_or_default();
+        let command = value["command"].as_str().unwrap_or_default().to_string();
+        let data = value.get_mut("data").map(Value::take);
+        Message { command, kind, id, data }
+    }
+}
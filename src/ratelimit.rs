@@ -0,0 +1,215 @@
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use crate::roles::Role;
+
+/// Параметры ведра токенов: `rate` команд в секунду в среднем и не больше `burst` подряд.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub rate: f64,
+    pub burst: u32,
+}
+
+/// Ограничения по командам протокола. Ограничение роли заменяет общее
+/// ограничение команды; команды без ограничения не учитываются.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimits {
+    pub commands: HashMap<String, RateLimit>,
+    pub roles: HashMap<Role, HashMap<String, RateLimit>>,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        let commands = [
+            ("auth", RateLimit { rate: 0.2, burst: 5 }),
+            ("message", RateLimit { rate: 20.0, burst: 40 }),
+            ("publish", RateLimit { rate: 20.0, burst: 40 }),
+            ("refresh", RateLimit { rate: 1.0, burst: 5 }),
+            ("logout", RateLimit { rate: 1.0, burst: 5 }),
+        ];
+        RateLimits {
+            commands: commands.into_iter().map(|(command, limit)| (command.to_string(), limit)).collect(),
+            roles: HashMap::new(),
+        }
+    }
+}
+
+impl RateLimits {
+    fn limit(&self, command: &str, role: Option<Role>) -> Option<RateLimit> {
+        role.and_then(|role| self.roles.get(&role)?.get(command))
+            .or_else(|| self.commands.get(command))
+            .copied()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let all = self.commands.iter().chain(self.roles.values().flatten());
+        for (command, limit) in all {
+            if limit.rate.is_nan() || limit.rate <= 0.0 || limit.burst == 0 {
+                return Err(format!("ограничение команды '{}': rate и burst должны быть больше нуля", command));
+            }
+        }
+        Ok(())
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        TokenBucket { tokens: f64::from(limit.burst), updated: now }
+    }
+
+    /// Забирает токен или возвращает, через сколько секунд он появится.
+    fn take(&mut self, limit: RateLimit, now: Instant) -> Result<(), f64> {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(f64::from(limit.burst));
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err((1.0 - self.tokens) / limit.rate)
+        }
+    }
+}
+
+/// Общие для всех соединений вёдра пользователей, чтобы несколько соединений
+/// одного пользователя не умножали его лимит.
+#[derive(Clone)]
+pub struct RateLimiter {
+    limits: Arc<RateLimits>,
+    users: Arc<Mutex<HashMap<(String, String), TokenBucket>>>,
+}
+
+/// Вёдра одного соединения; действуют и до аутентификации.
+#[derive(Default)]
+pub struct ConnectionLimits {
+    buckets: HashMap<String, TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        RateLimiter { limits: Arc::new(limits), users: Arc::default() }
+    }
+
+    /// Проверяет лимит соединения и, если соединение аутентифицировано, лимит
+    /// пользователя. Возвращает `Err` с временем ожидания в секундах.
+    pub fn check(
+        &self,
+        connection: &mut ConnectionLimits,
+        command: &str,
+        user: Option<(&str, Role)>,
+    ) -> Result<(), f64> {
+        let Some(limit) = self.limits.limit(command, user.map(|(_, role)| role)) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+
+        connection.buckets
+            .entry(command.to_string())
+            .or_insert_with(|| TokenBucket::new(limit, now))
+            .take(limit, now)?;
+
+        if let Some((username, _)) = user {
+            self.users.lock().unwrap()
+                .entry((username.to_string(), command.to_string()))
+                .or_insert_with(|| TokenBucket::new(limit, now))
+                .take(limit, now)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(RateLimits {
+            commands: HashMap::from([("message".to_string(), RateLimit { rate: 1.0, burst: 2 })]),
+            roles: HashMap::from([(
+                Role::Admin,
+                HashMap::from([("message".to_string(), RateLimit { rate: 1.0, burst: 4 })]),
+            )]),
+        })
+    }
+
+    #[test]
+    fn bucket_refills_at_rate_up_to_burst() {
+        let limit = RateLimit { rate: 2.0, burst: 3 };
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(limit, start);
+        for _ in 0..3 {
+            bucket.take(limit, start).unwrap();
+        }
+        assert_eq!(bucket.take(limit, start), Err(0.5));
+
+        let later = start + Duration::from_millis(500);
+        bucket.take(limit, later).unwrap();
+        assert!(bucket.take(limit, later).is_err());
+
+        let much_later = later + Duration::from_secs(60);
+        for _ in 0..3 {
+            bucket.take(limit, much_later).unwrap();
+        }
+        assert!(bucket.take(limit, much_later).is_err());
+    }
+
+    #[test]
+    fn commands_without_limit_are_not_counted() {
+        let limiter = limiter();
+        let mut connection = ConnectionLimits::default();
+        for _ in 0..100 {
+            limiter.check(&mut connection, "ping", Some(("alice", Role::Agent))).unwrap();
+        }
+    }
+
+    #[test]
+    fn role_limit_replaces_command_limit() {
+        let limiter = limiter();
+        let mut agent = ConnectionLimits::default();
+        let mut admin = ConnectionLimits::default();
+        for _ in 0..2 {
+            limiter.check(&mut agent, "message", Some(("alice", Role::Agent))).unwrap();
+        }
+        assert!(limiter.check(&mut agent, "message", Some(("alice", Role::Agent))).is_err());
+        for _ in 0..4 {
+            limiter.check(&mut admin, "message", Some(("root", Role::Admin))).unwrap();
+        }
+        assert!(limiter.check(&mut admin, "message", Some(("root", Role::Admin))).is_err());
+    }
+
+    #[test]
+    fn user_limit_is_shared_between_connections() {
+        let limiter = limiter();
+        let mut first = ConnectionLimits::default();
+        let mut second = ConnectionLimits::default();
+        limiter.check(&mut first, "message", Some(("alice", Role::Agent))).unwrap();
+        limiter.check(&mut second, "message", Some(("alice", Role::Agent))).unwrap();
+        assert!(limiter.check(&mut second, "message", Some(("alice", Role::Agent))).is_err());
+
+        // До аутентификации действует только лимит соединения.
+        let mut anonymous = ConnectionLimits::default();
+        limiter.check(&mut anonymous, "message", None).unwrap();
+        limiter.check(&mut anonymous, "message", None).unwrap();
+        assert!(limiter.check(&mut anonymous, "message", None).is_err());
+    }
+
+    #[test]
+    fn rejects_zero_limits() {
+        let mut limits = RateLimits::default();
+        assert!(limits.validate().is_ok());
+        limits.commands.insert("message".to_string(), RateLimit { rate: 0.0, burst: 1 });
+        assert!(limits.validate().is_err());
+    }
+}
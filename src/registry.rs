@@ -0,0 +1,303 @@
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    channels::{self, ChannelRules},
+    protocol::{Notice, PresenceChange, Recipient, Response},
+    roles::{Permission, Role},
+    unix_time, Message,
+};
+
+pub type Outbound = mpsc::Sender<Message>;
+
+/// Связь с задачей соединения, которую она передаёт в реестр при аутентификации.
+#[derive(Clone)]
+pub struct ConnectionHandle {
+    pub outbound: Outbound,
+    /// Время последнего кадра от клиента; обновляет задача соединения.
+    pub last_seen: Arc<AtomicU64>,
+    /// Отменяется, когда сессия соединения завершена; задача соединения закрывает его.
+    pub closed: CancellationToken,
+}
+
+struct Connection {
+    outbound: Outbound,
+    /// Сессия, по токену которой аутентифицировано соединение.
+    session: String,
+    role: Role,
+    groups: Vec<String>,
+    last_seen: Arc<AtomicU64>,
+    closed: CancellationToken,
+    subscriptions: Subscriptions,
+}
+
+/// Подписки соединения. Переживают повторную аутентификацию на том же соединении.
+#[derive(Default)]
+pub struct Subscriptions {
+    /// Соединение подписано на события `user_online`/`user_offline`.
+    presence: bool,
+    /// Каналы и шаблоны каналов, на которые подписано соединение.
+    channels: Vec<String>,
+}
+
+/// Сведения о пользователе, который сейчас в сети.
+#[derive(Serialize, Debug)]
+pub struct Presence {
+    pub username: String,
+    pub role: Role,
+    pub connections: usize,
+    pub last_seen: u64,
+}
+
+impl Connection {
+    /// Ставит сообщение в очередь соединения; при переполнении сообщение отбрасывается.
+    fn deliver(&self, username: &str, msg: &Message) -> bool {
+        match self.outbound.try_send(msg.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                eprintln!("Очередь сообщений клиента '{}' переполнена, сообщение отброшено.", username);
+                false
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+
+    fn matches(&self, username: &str, to: Option<&Recipient>) -> bool {
+        match to {
+            None => true,
+            Some(Recipient::User(name)) => name == username,
+            Some(Recipient::Group(group)) => self.groups.contains(group),
+            Some(Recipient::Role(role)) => self.role == *role,
+        }
+    }
+}
+
+/// Реестр авторизованных сессий: имя пользователя -> очереди исходящих
+/// сообщений его соединений, а также текущие роль и группы пользователя.
+/// Изменения роли и групп в базе переносятся сюда через `update_role` и `update_groups`.
+/// Сами сокеты принадлежат задачам-писателям.
+#[derive(Clone, Default)]
+pub struct AuthorizedClients {
+    clients: Arc<Mutex<HashMap<String, Vec<Connection>>>>,
+}
+
+impl AuthorizedClients {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Регистрирует соединение с подписками `subscriptions`, оставшимися от
+    /// предыдущей аутентификации на нём. Подписка на события присутствия
+    /// сохраняется, только если роль даёт на неё право; права на каналы
+    /// проверяются при каждой публикации.
+    pub fn register(
+        &self,
+        username: String,
+        session: String,
+        role: Role,
+        groups: Vec<String>,
+        handle: ConnectionHandle,
+        mut subscriptions: Subscriptions,
+    ) {
+        subscriptions.presence &= role.allows(Permission::ViewPresence);
+        let connection = Connection {
+            outbound: handle.outbound,
+            session,
+            role,
+            groups,
+            last_seen: handle.last_seen,
+            closed: handle.closed,
+            subscriptions,
+        };
+        let mut clients = self.clients.lock().unwrap();
+        let connections = clients.entry(username.clone()).or_default();
+        connections.push(connection);
+        let change = PresenceChange { username, role, connections: connections.len(), at: unix_time() };
+        notify_presence(&clients, Response::UserOnline(change));
+    }
+
+    /// Убирает соединение из реестра и возвращает его подписки.
+    pub fn unregister(&self, username: &str, outbound: &Outbound) -> Subscriptions {
+        self.remove_where(username, |c| c.outbound.same_channel(outbound))
+            .pop()
+            .map(|c| c.subscriptions)
+            .unwrap_or_default()
+    }
+
+    /// Закрывает соединения, аутентифицированные завершёнными сессиями
+    /// `sessions`: клиент получает `session_closed` с причиной `reason`.
+    pub fn close_sessions(&self, username: &str, sessions: &[String], reason: &str) {
+        let closed = self.remove_where(username, |c| sessions.contains(&c.session));
+        let notice = Response::SessionClosed(Notice { message: reason.to_string() }).into_message(None);
+        for connection in closed {
+            connection.deliver(username, &notice);
+            connection.closed.cancel();
+        }
+    }
+
+    /// Применяет новую роль к открытым соединениям пользователя. Подписка на
+    /// события присутствия снимается, если новая роль не даёт на неё права.
+    pub fn update_role(&self, username: &str, role: Role) {
+        let mut clients = self.clients.lock().unwrap();
+        for connection in clients.get_mut(username).into_iter().flatten() {
+            connection.role = role;
+            connection.subscriptions.presence &= role.allows(Permission::ViewPresence);
+        }
+    }
+
+    pub fn update_groups(&self, username: &str, groups: &[String]) {
+        let mut clients = self.clients.lock().unwrap();
+        for connection in clients.get_mut(username).into_iter().flatten() {
+            connection.groups = groups.to_vec();
+        }
+    }
+
+    /// Текущая роль соединения: `update_role` меняет её без повторной аутентификации.
+    pub fn role(&self, username: &str, outbound: &Outbound) -> Option<Role> {
+        let clients = self.clients.lock().unwrap();
+        clients.get(username)?.iter().find(|c| c.outbound.same_channel(outbound)).map(|c| c.role)
+    }
+
+    /// Переносит соединения на новую сессию после обновления токена.
+    pub fn replace_session(&self, username: &str, old: &str, new: &str) {
+        let mut clients = self.clients.lock().unwrap();
+        let Some(connections) = clients.get_mut(username) else {
+            return;
+        };
+        for connection in connections.iter_mut().filter(|c| c.session == old) {
+            connection.session = new.to_string();
+        }
+    }
+
+    // Удаляет подходящие соединения пользователя и сообщает подписчикам о каждом.
+    fn remove_where(&self, username: &str, remove: impl Fn(&Connection) -> bool) -> Vec<Connection> {
+        let mut clients = self.clients.lock().unwrap();
+        let Some(connections) = clients.get_mut(username) else {
+            return Vec::new();
+        };
+        let (removed, kept): (Vec<_>, Vec<_>) = connections.drain(..).partition(|c| remove(c));
+        *connections = kept;
+        let remaining = connections.len();
+        if remaining == 0 {
+            clients.remove(username);
+        }
+        for (i, connection) in removed.iter().enumerate() {
+            let change = PresenceChange {
+                username: username.to_string(),
+                role: connection.role,
+                connections: remaining + removed.len() - 1 - i,
+                at: unix_time(),
+            };
+            notify_presence(&clients, Response::UserOffline(change));
+        }
+        removed
+    }
+
+    /// Выполняет `update` для записи соединения. Возвращает `None`, если
+    /// соединение не аутентифицировано.
+    fn update_connection<T>(&self, outbound: &Outbound, update: impl FnOnce(&mut Connection) -> T) -> Option<T> {
+        let mut clients = self.clients.lock().unwrap();
+        clients.values_mut()
+            .flatten()
+            .find(|c| c.outbound.same_channel(outbound))
+            .map(update)
+    }
+
+    /// Включает или выключает для соединения события о входе и выходе
+    /// пользователей. Возвращает `false`, если соединение не аутентифицировано.
+    pub fn subscribe_presence(&self, outbound: &Outbound, enabled: bool) -> bool {
+        self.update_connection(outbound, |c| c.subscriptions.presence = enabled).is_some()
+    }
+
+    /// Подписывает соединение на канал или шаблон. Возвращает `None`, если
+    /// соединение не аутентифицировано, и `Some(false)`, если подписка уже есть.
+    pub fn subscribe_channel(&self, outbound: &Outbound, pattern: &str) -> Option<bool> {
+        self.update_connection(outbound, |c| {
+            if c.subscriptions.channels.iter().any(|s| s == pattern) {
+                return false;
+            }
+            c.subscriptions.channels.push(pattern.to_string());
+            true
+        })
+    }
+
+    /// Отменяет подписку. Возвращает `Some(false)`, если подписки не было.
+    pub fn unsubscribe_channel(&self, outbound: &Outbound, pattern: &str) -> Option<bool> {
+        self.update_connection(outbound, |c| {
+            let channels = &mut c.subscriptions.channels;
+            let before = channels.len();
+            channels.retain(|s| s != pattern);
+            channels.len() != before
+        })
+    }
+
+    /// Отправляет сообщение всем соединениям, подписанным на `channel`
+    /// напрямую или по шаблону. Возвращает число получателей.
+    ///
+    /// Права проверяются по самому каналу: подписка на `chat.*` не открывает
+    /// канал `chat.admins`, если для него задано более строгое правило.
+    pub fn publish(&self, channel: &str, msg: &Message, rules: &ChannelRules) -> usize {
+        let clients = self.clients.lock().unwrap();
+        let mut delivered = 0;
+        for (username, connections) in clients.iter() {
+            let subscribers = connections.iter()
+                .filter(|c| c.subscriptions.channels.iter().any(|pattern| channels::matches(pattern, channel)))
+                .filter(|c| rules.can_subscribe(channel, c.role));
+            for connection in subscribers {
+                if connection.deliver(username, msg) {
+                    delivered += 1;
+                }
+            }
+        }
+        delivered
+    }
+
+    /// Список пользователей в сети, упорядоченный по имени.
+    pub fn presence(&self) -> Vec<Presence> {
+        let clients = self.clients.lock().unwrap();
+        let mut presence: Vec<Presence> = clients.iter()
+            .map(|(username, connections)| Presence {
+                username: username.clone(),
+                role: connections[0].role,
+                connections: connections.len(),
+                last_seen: connections.iter().map(|c| c.last_seen.load(Ordering::Relaxed)).max().unwrap_or(0),
+            })
+            .collect();
+        presence.sort_by(|a, b| a.username.cmp(&b.username));
+        presence
+    }
+
+    /// Отправляет сообщение соединениям адресата `to` (всем, если он не задан),
+    /// роль которых подходит под `allowed`. Возвращает число соединений,
+    /// в очереди которых сообщение было поставлено.
+    pub fn send(&self, msg: &Message, to: Option<&Recipient>, allowed: impl Fn(Role) -> bool) -> usize {
+        let clients = self.clients.lock().unwrap();
+        let mut delivered = 0;
+        for (username, connections) in clients.iter() {
+            let recipients = connections.iter().filter(|c| allowed(c.role) && c.matches(username, to));
+            for connection in recipients {
+                if connection.deliver(username, msg) {
+                    delivered += 1;
+                }
+            }
+        }
+        delivered
+    }
+}
+
+fn notify_presence(clients: &HashMap<String, Vec<Connection>>, event: Response) {
+    let msg = event.into_message(None);
+    for (username, connections) in clients {
+        for connection in connections.iter().filter(|c| c.subscriptions.presence) {
+            connection.deliver(username, &msg);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// Роль пользователя. Новые и ранее созданные пользователи получают роль `Agent`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Центральная консоль: рассылает команды всем подключённым клиентам.
+    Admin,
+    /// Оператор только для чтения: получает отчёты агентов, но ничего не отправляет.
+    Operator,
+    /// Антивирусный агент: отправляет отчёты, которые видят только администраторы и операторы.
+    #[default]
+    Agent,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Permission {
+    /// Отправка `"message"` всем подключённым клиентам.
+    Broadcast,
+    /// Отправка `"message"` только получателям отчётов.
+    Report,
+    /// Получение отчётов агентов.
+    ReceiveReports,
+    /// Просмотр списка пользователей в сети.
+    ViewPresence,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Admin, Role::Operator, Role::Agent];
+
+    pub fn permissions(self) -> &'static [Permission] {
+        match self {
+            Role::Admin => &[Permission::Broadcast, Permission::ReceiveReports, Permission::ViewPresence],
+            Role::Operator => &[Permission::ReceiveReports, Permission::ViewPresence],
+            Role::Agent => &[Permission::Report],
+        }
+    }
+
+    pub fn allows(self, permission: Permission) -> bool {
+        self.permissions().contains(&permission)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Operator => "operator",
+            Role::Agent => "agent",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Role::ALL
+            .into_iter()
+            .find(|role| role.as_str() == s)
+            .ok_or_else(|| format!("неизвестная роль '{}', ожидается admin, operator или agent", s))
+    }
+}
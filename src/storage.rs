@@ -0,0 +1,293 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::User;
+
+/// Число записей в журнале, после которого база переписывается в новый снимок.
+const COMPACT_AFTER: usize = 1000;
+
+/// Хранилище базы пользователей. `load` вызывается один раз при запуске,
+/// `save` — после каждого изменения пользователя, `snapshot` — при остановке.
+pub trait UserStore: Send {
+    fn load(&mut self) -> io::Result<HashMap<String, User>>;
+    /// Сохраняет изменение пользователя `username`; если его нет в `users`, он удалён.
+    fn save(&mut self, users: &HashMap<String, User>, username: &str) -> io::Result<()>;
+    /// Записывает базу целиком.
+    fn snapshot(&mut self, users: &HashMap<String, User>) -> io::Result<()>;
+}
+
+/// Хранит пользователей только в памяти процесса.
+pub struct MemoryStore;
+
+impl UserStore for MemoryStore {
+    fn load(&mut self) -> io::Result<HashMap<String, User>> {
+        Ok(HashMap::new())
+    }
+
+    fn save(&mut self, _users: &HashMap<String, User>, _username: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn snapshot(&mut self, _users: &HashMap<String, User>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct JournalEntry<'a> {
+    username: &'a str,
+    user: Option<&'a User>,
+}
+
+#[derive(Deserialize)]
+struct JournalRecord {
+    username: String,
+    user: Option<User>,
+}
+
+/// Хранит базу в снимке (`users.json`) и журнале изменений рядом с ним
+/// (`users.journal`). Изменение пользователя дописывается в журнал одной
+/// строкой, поэтому стоимость записи не зависит от размера базы. При запуске
+/// журнал применяется к снимку, после чего база записывается в новый снимок,
+/// а журнал очищается; то же происходит, когда журнал вырастает до `COMPACT_AFTER` записей.
+pub struct FileStore {
+    path: PathBuf,
+    journal_path: PathBuf,
+    journal: Option<File>,
+    entries: usize,
+}
+
+impl FileStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        FileStore {
+            journal_path: path.with_extension("journal"),
+            path,
+            journal: None,
+            entries: 0,
+        }
+    }
+
+    // Применяет журнал к базе. Запись, оборванная сбоем посреди дописывания,
+    // может быть только последней, и она пропускается.
+    fn replay(&self, users: &mut HashMap<String, User>) -> io::Result<()> {
+        let data = match fs::read(&self.journal_path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let lines: Vec<&[u8]> = data.split(|b| *b == b'\n').filter(|line| !line.is_empty()).collect();
+        for (i, line) in lines.iter().enumerate() {
+            match serde_json::from_slice::<JournalRecord>(line) {
+                Ok(JournalRecord { username, user: Some(user) }) => {
+                    users.insert(username, user);
+                }
+                Ok(JournalRecord { username, user: None }) => {
+                    users.remove(&username);
+                }
+                Err(_) if i + 1 == lines.len() => {
+                    eprintln!("Последняя запись журнала '{}' повреждена и пропущена.", self.journal_path.display());
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn create_options() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+}
+
+// Синхронизирует каталог, чтобы переименование или создание файла в нём пережило сбой питания.
+fn sync_parent(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let parent = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        File::open(parent)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+impl UserStore for FileStore {
+    fn load(&mut self) -> io::Result<HashMap<String, User>> {
+        let mut users = match fs::read(&self.path) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        self.replay(&mut users)?;
+        self.snapshot(&users)?;
+        Ok(users)
+    }
+
+    fn save(&mut self, users: &HashMap<String, User>, username: &str) -> io::Result<()> {
+        if self.entries >= COMPACT_AFTER {
+            return self.snapshot(users);
+        }
+        let Some(journal) = &mut self.journal else {
+            return self.snapshot(users);
+        };
+        let mut line = serde_json::to_vec(&JournalEntry { username, user: users.get(username) })?;
+        line.push(b'\n');
+        if let Err(e) = journal.write_all(&line).and_then(|_| journal.sync_data()) {
+            // Запись могла оборваться посреди строки: следующее изменение
+            // запишет снимок и начнёт журнал заново.
+            self.journal = None;
+            return Err(e);
+        }
+        self.entries += 1;
+        Ok(())
+    }
+
+    // Снимок пишется во временный файл, который затем атомарно переименовывается
+    // поверх основного; журнал очищается только после этого.
+    fn snapshot(&mut self, users: &HashMap<String, User>) -> io::Result<()> {
+        self.journal = None;
+        let data = serde_json::to_vec_pretty(users)?;
+        let tmp_path = self.path.with_extension("tmp");
+        let mut file = create_options().truncate(true).open(&tmp_path)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        sync_parent(&self.path)?;
+
+        let journal = create_options().append(true).open(&self.journal_path)?;
+        journal.set_len(0)?;
+        journal.sync_all()?;
+        sync_parent(&self.journal_path)?;
+        self.journal = Some(journal);
+        self.entries = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{outbox::Outbox, roles::Role};
+
+    fn user(password_hash: &str) -> User {
+        User {
+            password_hash: password_hash.to_string(),
+            role: Role::default(),
+            groups: Vec::new(),
+            sessions: Vec::new(),
+            outbox: Outbox::default(),
+        }
+    }
+
+    fn hashes(users: &HashMap<String, User>) -> Vec<(String, String)> {
+        let mut hashes: Vec<_> = users.iter().map(|(name, user)| (name.clone(), user.password_hash.clone())).collect();
+        hashes.sort();
+        hashes
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(name, hash)| (name.to_string(), hash.to_string())).collect()
+    }
+
+    // Открывает хранилище и применяет изменения через журнал, не записывая снимок при закрытии.
+    fn apply(path: &Path, changes: &[(&str, Option<&str>)]) {
+        let mut store = FileStore::new(path);
+        let mut users = store.load().unwrap();
+        for (name, hash) in changes {
+            match hash {
+                Some(hash) => users.insert(name.to_string(), user(hash)),
+                None => users.remove(*name),
+            };
+            store.save(&users, name).unwrap();
+        }
+    }
+
+    #[test]
+    fn replays_journal_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("users.json");
+        apply(&path, &[("alice", Some("a1")), ("bob", Some("b1")), ("alice", Some("a2")), ("bob", None)]);
+        assert!(fs::metadata(path.with_extension("journal")).unwrap().len() > 0);
+
+        let users = FileStore::new(&path).load().unwrap();
+        assert_eq!(hashes(&users), pairs(&[("alice", "a2")]));
+        // Загрузка переписывает снимок и очищает журнал.
+        assert_eq!(fs::metadata(path.with_extension("journal")).unwrap().len(), 0);
+        let snapshot: HashMap<String, User> = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(hashes(&snapshot), pairs(&[("alice", "a2")]));
+    }
+
+    #[test]
+    fn skips_torn_last_journal_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("users.json");
+        apply(&path, &[("alice", Some("a1")), ("bob", Some("b1"))]);
+        let mut journal = OpenOptions::new().append(true).open(path.with_extension("journal")).unwrap();
+        journal.write_all(br#"{"username":"carol","user":{"passw"#).unwrap();
+
+        let users = FileStore::new(&path).load().unwrap();
+        assert_eq!(hashes(&users), pairs(&[("alice", "a1"), ("bob", "b1")]));
+    }
+
+    #[test]
+    fn refuses_corrupt_entry_before_the_last() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("users.json");
+        apply(&path, &[("alice", Some("a1"))]);
+        let mut journal = OpenOptions::new().append(true).open(path.with_extension("journal")).unwrap();
+        journal.write_all(b"garbage\n{\"username\":\"alice\",\"user\":null}\n").unwrap();
+
+        assert!(FileStore::new(&path).load().is_err());
+    }
+
+    #[test]
+    fn compacts_journal_into_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("users.json");
+        let mut store = FileStore::new(&path);
+        let mut users = store.load().unwrap();
+        users.insert("alice".to_string(), user("a1"));
+        store.save(&users, "alice").unwrap();
+        assert_eq!(store.entries, 1);
+
+        store.entries = COMPACT_AFTER;
+        users.insert("bob".to_string(), user("b1"));
+        store.save(&users, "bob").unwrap();
+        assert_eq!(store.entries, 0);
+        assert_eq!(fs::metadata(path.with_extension("journal")).unwrap().len(), 0);
+        let snapshot: HashMap<String, User> = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(hashes(&snapshot), pairs(&[("alice", "a1"), ("bob", "b1")]));
+    }
+
+    #[test]
+    fn starts_new_journal_after_failed_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("users.json");
+        let mut store = FileStore::new(&path);
+        let mut users = store.load().unwrap();
+
+        // Журнал, открытый только для чтения, не даёт дописать запись.
+        store.journal = Some(File::open(path.with_extension("journal")).unwrap());
+        users.insert("alice".to_string(), user("a1"));
+        assert!(store.save(&users, "alice").is_err());
+        assert!(store.journal.is_none());
+
+        users.insert("bob".to_string(), user("b1"));
+        store.save(&users, "bob").unwrap();
+        assert!(store.journal.is_some());
+        users.insert("carol".to_string(), user("c1"));
+        store.save(&users, "carol").unwrap();
+
+        let users = FileStore::new(&path).load().unwrap();
+        assert_eq!(hashes(&users), pairs(&[("alice", "a1"), ("bob", "b1"), ("carol", "c1")]));
+    }
+}
@@ -0,0 +1,329 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+use tokio::time::{interval, Duration};
+use tokio_rustls::{
+    rustls::{
+        crypto::{ring, CryptoProvider},
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier},
+        sign::CertifiedKey,
+        RootCertStore, ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct TlsSettings {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    pub client_ca_path: Option<PathBuf>,
+}
+
+/// Отдаёт текущий сертификат сервера и позволяет заменить его без перезапуска.
+struct ReloadingCert {
+    settings: TlsSettings,
+    provider: Arc<CryptoProvider>,
+    current: RwLock<(Arc<CertifiedKey>, Option<SystemTime>)>,
+}
+
+impl fmt::Debug for ReloadingCert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReloadingCert")
+            .field("cert_path", &self.settings.cert_path)
+            .field("key_path", &self.settings.key_path)
+            .finish()
+    }
+}
+
+impl ResolvesServerCert for ReloadingCert {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().0.clone())
+    }
+}
+
+impl ReloadingCert {
+    fn reload_if_changed(&self) {
+        let modified = modified(&self.settings);
+        if modified == self.current.read().unwrap().1 {
+            return;
+        }
+        match load_certified_key(&self.settings, &self.provider) {
+            Ok(key) => {
+                *self.current.write().unwrap() = (Arc::new(key), modified);
+                println!("TLS-сертификат перезагружен.");
+            }
+            Err(e) => eprintln!("Ошибка при перезагрузке TLS-сертификата: {}", e),
+        }
+    }
+}
+
+fn modified(settings: &TlsSettings) -> Option<SystemTime> {
+    let cert = std::fs::metadata(&settings.cert_path).and_then(|m| m.modified()).ok();
+    let key = std::fs::metadata(&settings.key_path).and_then(|m| m.modified()).ok();
+    cert.max(key)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid_data(format!("'{}': {}", path.display(), e)))?;
+    if certs.is_empty() {
+        return Err(invalid_data(format!("'{}': сертификаты не найдены", path.display())));
+    }
+    Ok(certs)
+}
+
+fn load_certified_key(settings: &TlsSettings, provider: &CryptoProvider) -> io::Result<CertifiedKey> {
+    let certs = load_certs(&settings.cert_path)?;
+    let key = PrivateKeyDer::from_pem_file(&settings.key_path)
+        .map_err(|e| invalid_data(format!("'{}': {}", settings.key_path.display(), e)))?;
+    CertifiedKey::from_der(certs, key, provider)
+        .map_err(|e| invalid_data(format!("'{}': {}", settings.key_path.display(), e)))
+}
+
+/// Создаёт TLS-акцептор и запускает фоновую задачу, которая перечитывает
+/// сертификат и ключ при изменении файлов.
+pub fn acceptor(settings: TlsSettings) -> io::Result<TlsAcceptor> {
+    let (config, resolver) = server_config(settings)?;
+    tokio::spawn(async move {
+        let mut ticker = interval(RELOAD_INTERVAL);
+        loop {
+            ticker.tick().await;
+            resolver.reload_if_changed();
+        }
+    });
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+// Собирает конфигурацию сервера; сертификат для неё отдаёт возвращаемый `ReloadingCert`.
+fn server_config(settings: TlsSettings) -> io::Result<(ServerConfig, Arc<ReloadingCert>)> {
+    let provider = Arc::new(ring::default_provider());
+
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| invalid_data(e.to_string()))?;
+    let builder = match &settings.client_ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(path)? {
+                roots.add(cert).map_err(|e| invalid_data(format!("'{}': {}", path.display(), e)))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                .build()
+                .map_err(|e| invalid_data(e.to_string()))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let key = load_certified_key(&settings, &provider)?;
+    let resolver = Arc::new(ReloadingCert {
+        current: RwLock::new((Arc::new(key), modified(&settings))),
+        settings,
+        provider,
+    });
+
+    Ok((builder.with_cert_resolver(resolver.clone()), resolver))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+    use std::fs::{self, File};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio_rustls::{
+        rustls::{
+            pki_types::{PrivatePkcs8KeyDer, ServerName},
+            ClientConfig,
+        },
+        TlsConnector,
+    };
+
+    struct Identity {
+        cert: rcgen::Certificate,
+        key: KeyPair,
+    }
+
+    impl Identity {
+        fn der(&self) -> CertificateDer<'static> {
+            self.cert.der().clone()
+        }
+
+        fn key_der(&self) -> PrivateKeyDer<'static> {
+            PrivatePkcs8KeyDer::from(self.key.serialize_der()).into()
+        }
+    }
+
+    fn server_identity() -> Identity {
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        Identity { cert: generated.cert, key: generated.key_pair }
+    }
+
+    fn client_ca() -> Identity {
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let key = KeyPair::generate().unwrap();
+        Identity { cert: params.self_signed(&key).unwrap(), key }
+    }
+
+    fn client_identity(ca: &Identity) -> Identity {
+        let mut params = CertificateParams::new(vec!["agent".to_string()]).unwrap();
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        let key = KeyPair::generate().unwrap();
+        Identity { cert: params.signed_by(&key, &ca.cert, &ca.key).unwrap(), key }
+    }
+
+    // Записывает сертификат и ключ сервера (и, если задан, CA клиентов) в `dir`.
+    fn settings(dir: &Path, server: &Identity, client_ca: Option<&Identity>) -> TlsSettings {
+        let settings = TlsSettings {
+            cert_path: dir.join("cert.pem"),
+            key_path: dir.join("key.pem"),
+            client_ca_path: client_ca.map(|_| dir.join("ca.pem")),
+        };
+        write_server_identity(&settings, server);
+        if let (Some(path), Some(ca)) = (&settings.client_ca_path, client_ca) {
+            fs::write(path, ca.cert.pem()).unwrap();
+        }
+        settings
+    }
+
+    fn write_server_identity(settings: &TlsSettings, server: &Identity) {
+        fs::write(&settings.cert_path, server.cert.pem()).unwrap();
+        fs::write(&settings.key_path, server.key.serialize_pem()).unwrap();
+    }
+
+    fn client_config(trusted: &[&Identity], identity: Option<&Identity>) -> ClientConfig {
+        let mut roots = RootCertStore::empty();
+        for cert in trusted {
+            roots.add(cert.der()).unwrap();
+        }
+        let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        match identity {
+            Some(identity) => builder.with_client_auth_cert(vec![identity.der()], identity.key_der()).unwrap(),
+            None => builder.with_no_client_auth(),
+        }
+    }
+
+    // Проводит рукопожатие в памяти и передаёт строку от клиента серверу.
+    // Возвращает принятую сервером строку и сертификат, который показал сервер.
+    async fn exchange(config: Arc<ServerConfig>, client: ClientConfig) -> io::Result<(String, CertificateDer<'static>)> {
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let acceptor = TlsAcceptor::from(config);
+        let connector = TlsConnector::from(Arc::new(client));
+
+        let server = async {
+            let mut stream = BufReader::new(acceptor.accept(server_io).await?);
+            let mut line = String::new();
+            stream.read_line(&mut line).await?;
+            Ok::<_, io::Error>(line)
+        };
+        let client = async {
+            let name = ServerName::try_from("localhost").unwrap();
+            let mut stream = connector.connect(name, client_io).await?;
+            stream.write_all(b"hello\n").await?;
+            stream.flush().await?;
+            let cert = stream.get_ref().1.peer_certificates().unwrap()[0].clone();
+            Ok::<_, io::Error>((stream, cert))
+        };
+
+        let (server, client) = tokio::join!(server, client);
+        let line = server?;
+        let (_stream, cert) = client?;
+        Ok((line, cert))
+    }
+
+    #[tokio::test]
+    async fn serves_certificate_from_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = server_identity();
+        let (config, _) = server_config(settings(dir.path(), &server, None)).unwrap();
+
+        let (line, cert) = exchange(Arc::new(config), client_config(&[&server], None)).await.unwrap();
+        assert_eq!(line, "hello\n");
+        assert_eq!(cert, server.der());
+    }
+
+    #[tokio::test]
+    async fn accepts_client_certificate_signed_by_client_ca() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = server_identity();
+        let ca = client_ca();
+        let client = client_identity(&ca);
+        let (config, _) = server_config(settings(dir.path(), &server, Some(&ca))).unwrap();
+
+        let (line, _) = exchange(Arc::new(config), client_config(&[&server], Some(&client))).await.unwrap();
+        assert_eq!(line, "hello\n");
+    }
+
+    #[tokio::test]
+    async fn rejects_client_without_certificate_when_client_ca_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = server_identity();
+        let ca = client_ca();
+        let (config, _) = server_config(settings(dir.path(), &server, Some(&ca))).unwrap();
+
+        assert!(exchange(Arc::new(config), client_config(&[&server], None)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_client_certificate_from_another_ca() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = server_identity();
+        let ca = client_ca();
+        let stranger = client_identity(&client_ca());
+        let (config, _) = server_config(settings(dir.path(), &server, Some(&ca))).unwrap();
+
+        assert!(exchange(Arc::new(config), client_config(&[&server], Some(&stranger))).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn reloads_certificate_after_files_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = server_identity();
+        let new = server_identity();
+        let settings = settings(dir.path(), &old, None);
+        let (config, resolver) = server_config(settings).unwrap();
+        let config = Arc::new(config);
+        let trusted = [&old, &new];
+
+        let (_, cert) = exchange(config.clone(), client_config(&trusted, None)).await.unwrap();
+        assert_eq!(cert, old.der());
+
+        write_server_identity(&resolver.settings, &new);
+        // Время изменения сдвигается явно: файловая система может не различить две записи подряд.
+        let later = SystemTime::now() + Duration::from_secs(10);
+        File::options().write(true).open(&resolver.settings.cert_path).unwrap().set_modified(later).unwrap();
+        resolver.reload_if_changed();
+
+        let (_, cert) = exchange(config.clone(), client_config(&trusted, None)).await.unwrap();
+        assert_eq!(cert, new.der());
+    }
+
+    #[tokio::test]
+    async fn keeps_certificate_when_reload_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = server_identity();
+        let (config, resolver) = server_config(settings(dir.path(), &server, None)).unwrap();
+
+        fs::write(&resolver.settings.cert_path, "not a certificate").unwrap();
+        let later = SystemTime::now() + Duration::from_secs(10);
+        File::options().write(true).open(&resolver.settings.cert_path).unwrap().set_modified(later).unwrap();
+        resolver.reload_if_changed();
+
+        let (_, cert) = exchange(Arc::new(config), client_config(&[&server], None)).await.unwrap();
+        assert_eq!(cert, server.der());
+    }
+}
@@ -0,0 +1,54 @@
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+const KEY_LENGTH: usize = 32;
+
+/// Вычисляет HMAC-SHA256 токенов на секретном ключе сервера. В базе хранится
+/// только результат, поэтому утечка базы не позволяет выдать себя за пользователя.
+pub struct TokenHasher {
+    key: Vec<u8>,
+}
+
+impl TokenHasher {
+    /// Читает ключ из файла или создаёт новый случайный ключ, если файла нет.
+    pub fn load_or_create(path: &Path) -> io::Result<Self> {
+        match fs::read(path) {
+            Ok(key) if key.len() >= KEY_LENGTH => Ok(TokenHasher { key }),
+            Ok(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Ключ в '{}' короче {} байт", path.display(), KEY_LENGTH),
+            )),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let mut key = vec![0u8; KEY_LENGTH];
+                rand::rngs::OsRng.fill_bytes(&mut key);
+
+                let mut options = OpenOptions::new();
+                options.write(true).create_new(true);
+                #[cfg(unix)]
+                std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+                let mut file = options.open(path)?;
+                file.write_all(&key)?;
+                file.sync_all()?;
+                Ok(TokenHasher { key })
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn hash(&self, token: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key)
+            .expect("HMAC принимает ключ любой длины");
+        mac.update(token.as_bytes());
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}